@@ -0,0 +1,103 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+use RuntimeOpts;
+use dac::DacConfig;
+use net2::UdpBuilder;
+use protocol::Broadcast;
+use protocol::DacStatus;
+use shutdown::Shutdown;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+/// Send a UDP broadcast announcing the EtherDream to the network.
+pub fn broadcast_thread(opts: &RuntimeOpts, config: &DacConfig,
+                        status: Arc<RwLock<DacStatus>>, shutdown: &Shutdown) {
+  let udp = UdpBuilder::new_v4().unwrap();
+  udp.reuse_address(true).unwrap();
+
+  let socket = udp.bind((config.bind_address, 0)).unwrap();
+  socket.set_broadcast(true).unwrap();
+
+  let multicast_ip = opts.broadcast_address;
+  let multicast_socket =
+      SocketAddr::new(IpAddr::V4(multicast_ip), opts.broadcast_port);
+
+  while !shutdown.wait(opts.broadcast_interval) {
+    let status = match status.read() {
+      Err(_) => continue, // Ignore lock errors.
+      Ok(status) => status.clone(),
+    };
+
+    let broadcast = make_broadcast(opts, config, status);
+    socket.send_to(&broadcast.serialize(), multicast_socket).unwrap();
+  }
+}
+
+/// The broadcast announcing a DAC with the given status.
+fn make_broadcast(opts: &RuntimeOpts, config: &DacConfig, status: DacStatus)
+                  -> Broadcast {
+  Broadcast {
+    mac_address: config.mac_address,
+    hw_revision: opts.hw_revision,
+    sw_revision: opts.sw_revision,
+    buffer_capacity: opts.buffer_capacity,
+    max_point_rate: opts.max_point_rate,
+    status: status,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use RuntimeOpts;
+  use protocol::DacStatus;
+  use protocol::PLAYBACK_PLAYING;
+
+  #[test]
+  fn test_broadcast_bytes() {
+    let opts = RuntimeOpts {
+      num_dacs: 2,
+      mac_address: [0x10, 0x20, 0x30, 0x40, 0x50, 0x60],
+      hw_revision: 0x0102,
+      sw_revision: 0x0304,
+      buffer_capacity: 1_800,
+      max_point_rate: 100_000,
+      ..RuntimeOpts::default()
+    };
+    let config = opts.dac_configs().unwrap().remove(1);
+
+    let mut status = DacStatus::empty();
+    status.playback_state = PLAYBACK_PLAYING;
+    status.playback_flags = 0x0203;
+    status.buffer_fullness = 0x0405;
+    status.point_rate = 30_000;
+    status.point_count = 0x01020304;
+
+    let bytes = make_broadcast(&opts, &config, status).serialize();
+
+    let expected : Vec<u8> = vec![
+      // MAC address, with the last byte offset for the second DAC.
+      0x10, 0x20, 0x30, 0x40, 0x50, 0x61,
+      // Hardware and software revisions.
+      0x02, 0x01, 0x04, 0x03,
+      // Buffer capacity, 1800.
+      0x08, 0x07,
+      // Max point rate, 100000.
+      0xa0, 0x86, 0x01, 0x00,
+      // Protocol, light engine state, playback state, source.
+      0x00, 0x00, 0x02, 0x00,
+      // Light engine, playback and source flags.
+      0x00, 0x00, 0x03, 0x02, 0x00, 0x00,
+      // Buffer fullness.
+      0x05, 0x04,
+      // Point rate, 30000.
+      0x30, 0x75, 0x00, 0x00,
+      // Point count.
+      0x04, 0x03, 0x02, 0x01,
+    ];
+
+    assert_eq!(bytes, expected);
+  }
+}
@@ -0,0 +1,118 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Which part of the ILDA coordinate space is shown, for zooming in on
+//! detail and panning around.
+
+use ilda::limit;
+
+/// Least and most a view can be zoomed.
+const MIN_ZOOM : f64 = 1.0;
+const MAX_ZOOM : f64 = 256.0;
+
+/// Zoom and pan of the drawn points. Coordinates are in ILDA units, and
+/// tile positions in pixels from the top left of a DAC's tile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+  /// Magnification; 1.0 fits the whole coordinate space in the tile.
+  pub zoom: f64,
+  /// ILDA coordinate at the center of the tile.
+  pub center: [f64; 2],
+}
+
+impl Camera {
+  /// CTOR. Shows the whole coordinate space.
+  pub fn new() -> Camera {
+    Camera {
+      zoom: 1.0,
+      center: [0.0, 0.0],
+    }
+  }
+
+  /// Pixels per ILDA unit, horizontally and vertically, in a tile.
+  pub fn scale(&self, width: f64, height: f64) -> [f64; 2] {
+    [width / limit::WIDTH as f64 * self.zoom,
+     height / limit::HEIGHT as f64 * self.zoom]
+  }
+
+  /// Position in a tile of an ILDA coordinate.
+  pub fn to_tile(&self, x: f64, y: f64, width: f64, height: f64) -> [f64; 2] {
+    let scale = self.scale(width, height);
+
+    // NB: Have to invert y since the vertical coordinate system transforms.
+    [(x - self.center[0]) * scale[0] + width / 2.0,
+     (self.center[1] - y) * scale[1] + height / 2.0]
+  }
+
+  /// ILDA coordinate at a position in a tile.
+  pub fn to_ilda(&self, tile_x: f64, tile_y: f64, width: f64, height: f64)
+                 -> [f64; 2] {
+    let scale = self.scale(width, height);
+
+    [(tile_x - width / 2.0) / scale[0] + self.center[0],
+     self.center[1] - (tile_y - height / 2.0) / scale[1]]
+  }
+
+  /// Zoom in (factor above 1.0) or out, keeping the coordinate under the
+  /// given tile position in place.
+  pub fn zoom_at(&mut self, factor: f64, tile_x: f64, tile_y: f64,
+                 width: f64, height: f64) {
+    let before = self.to_ilda(tile_x, tile_y, width, height);
+
+    self.zoom = (self.zoom * factor).max(MIN_ZOOM).min(MAX_ZOOM);
+
+    let after = self.to_ilda(tile_x, tile_y, width, height);
+    self.center[0] += before[0] - after[0];
+    self.center[1] += before[1] - after[1];
+  }
+
+  /// Move the view along with a drag of the given pixels.
+  pub fn pan(&mut self, dx: f64, dy: f64, width: f64, height: f64) {
+    let scale = self.scale(width, height);
+    self.center[0] -= dx / scale[0];
+    self.center[1] += dy / scale[1];
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_mapping() {
+    let camera = Camera::new();
+
+    assert_eq!(camera.to_tile(0.0, 0.0, 600.0, 400.0), [300.0, 200.0]);
+    assert_eq!(camera.to_ilda(300.0, 200.0, 600.0, 400.0), [0.0, 0.0]);
+
+    let corner = camera.to_tile(-32767.5, 32767.5, 600.0, 400.0);
+    assert_eq!(corner, [0.0, 0.0]);
+  }
+
+  #[test]
+  fn test_zoom_at() {
+    let mut camera = Camera::new();
+    let before = camera.to_ilda(100.0, 50.0, 600.0, 600.0);
+
+    camera.zoom_at(4.0, 100.0, 50.0, 600.0, 600.0);
+    assert_eq!(camera.zoom, 4.0);
+
+    let after = camera.to_ilda(100.0, 50.0, 600.0, 600.0);
+    assert!((before[0] - after[0]).abs() < 1e-6);
+    assert!((before[1] - after[1]).abs() < 1e-6);
+
+    // Can't zoom out past the whole coordinate space.
+    camera.zoom_at(0.01, 100.0, 50.0, 600.0, 600.0);
+    assert_eq!(camera.zoom, 1.0);
+  }
+
+  #[test]
+  fn test_pan() {
+    let mut camera = Camera::new();
+    camera.pan(60.0, 60.0, 600.0, 600.0);
+
+    // Dragging right and down brings the left and top into view.
+    assert!(camera.center[0] < 0.0);
+    assert!(camera.center[1] > 0.0);
+    assert_eq!(camera.to_tile(0.0, 0.0, 600.0, 600.0), [360.0, 360.0]);
+  }
+}
@@ -0,0 +1,257 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Session capture files. A capture holds every command a client sent,
+//! byte for byte, along with when it arrived and when clients connected
+//! and disconnected.
+//!
+//! All integers are little endian. The file starts with a header:
+//!
+//!   - 4 bytes: magic, "EDCP"
+//!   - u16: format version (1)
+//!   - u64: wall clock start of the capture, in microseconds since the epoch
+//!
+//! Followed by any number of records:
+//!
+//!   - u8: record kind (1 = connection open, 2 = connection close,
+//!         3 = command)
+//!   - u8: index of the virtual DAC
+//!   - u64: microseconds since the start of the capture
+//!   - u32: payload length
+//!   - payload: the client's IPv4 address and u16 port for an open record,
+//!     nothing for a close record, or the raw command bytes (including any
+//!     point data) for a command record.
+
+use byteorder::LittleEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use error::EmulatorError;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const MAGIC : &'static [u8; 4] = b"EDCP";
+const VERSION : u16 = 1;
+
+/// What a capture record describes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordKind {
+  /// A client connected.
+  Open,
+  /// A client disconnected.
+  Close,
+  /// A client sent a command.
+  Command,
+}
+
+impl RecordKind {
+  fn value(&self) -> u8 {
+    match *self {
+      RecordKind::Open => 1,
+      RecordKind::Close => 2,
+      RecordKind::Command => 3,
+    }
+  }
+
+  fn from_value(value: u8) -> Result<RecordKind, EmulatorError> {
+    match value {
+      1 => Ok(RecordKind::Open),
+      2 => Ok(RecordKind::Close),
+      3 => Ok(RecordKind::Command),
+      _ => Err(EmulatorError::ParseError),
+    }
+  }
+}
+
+/// A single event in a capture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+  pub kind: RecordKind,
+  /// Index of the virtual DAC the event happened on.
+  pub dac: u8,
+  /// Time since the start of the capture.
+  pub timestamp: Duration,
+  pub payload: Vec<u8>,
+}
+
+/// Writes a capture file. Shared by every DAC in the process.
+pub struct Recorder {
+  writer: Mutex<BufWriter<File>>,
+  start: Instant,
+}
+
+impl Recorder {
+  /// Create (or truncate) a capture file and write its header.
+  pub fn create(path: &str) -> Result<Recorder, EmulatorError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+
+    writer.write_all(MAGIC)?;
+    writer.write_u16::<LittleEndian>(VERSION)?;
+    writer.write_u64::<LittleEndian>(micros(since_epoch))?;
+
+    Ok(Recorder {
+      writer: Mutex::new(writer),
+      start: Instant::now(),
+    })
+  }
+
+  /// Record a client connecting.
+  pub fn open(&self, dac: u8, peer: SocketAddr) -> Result<(), EmulatorError> {
+    let mut payload = Vec::new();
+    if let SocketAddr::V4(addr) = peer {
+      payload.extend_from_slice(&addr.ip().octets());
+      payload.write_u16::<LittleEndian>(addr.port())?;
+    }
+    self.write(RecordKind::Open, dac, &payload)
+  }
+
+  /// Record a client disconnecting. Also flushes the file.
+  pub fn close(&self, dac: u8) -> Result<(), EmulatorError> {
+    self.write(RecordKind::Close, dac, &[])?;
+    self.writer.lock()?.flush()?;
+    Ok(())
+  }
+
+  /// Record the raw bytes of a command.
+  pub fn command(&self, dac: u8, bytes: &[u8]) -> Result<(), EmulatorError> {
+    self.write(RecordKind::Command, dac, bytes)
+  }
+
+  fn write(&self, kind: RecordKind, dac: u8, payload: &[u8])
+      -> Result<(), EmulatorError> {
+    let timestamp = micros(self.start.elapsed());
+    let mut writer = self.writer.lock()?;
+
+    writer.write_u8(kind.value())?;
+    writer.write_u8(dac)?;
+    writer.write_u64::<LittleEndian>(timestamp)?;
+    writer.write_u32::<LittleEndian>(payload.len() as u32)?;
+    writer.write_all(payload)?;
+    Ok(())
+  }
+}
+
+/// Reads a capture file one record at a time.
+pub struct CaptureReader {
+  reader: BufReader<File>,
+  /// Wall clock start of the capture, since the epoch.
+  pub started: Duration,
+}
+
+impl CaptureReader {
+  /// Open a capture file and check its header.
+  pub fn open(path: &str) -> Result<CaptureReader, EmulatorError> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC || reader.read_u16::<LittleEndian>()? != VERSION {
+      return Err(EmulatorError::ParseError);
+    }
+
+    let started = from_micros(reader.read_u64::<LittleEndian>()?);
+
+    Ok(CaptureReader {
+      reader: reader,
+      started: started,
+    })
+  }
+
+  /// Read the next record, or None at the end of the capture.
+  pub fn next_record(&mut self) -> Result<Option<Record>, EmulatorError> {
+    let kind = match self.reader.read_u8() {
+      Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+      Err(e) => return Err(EmulatorError::from(e)),
+      Ok(kind) => RecordKind::from_value(kind)?,
+    };
+
+    let dac = self.reader.read_u8()?;
+    let timestamp = from_micros(self.reader.read_u64::<LittleEndian>()?);
+    let length = self.reader.read_u32::<LittleEndian>()?;
+
+    let mut payload = vec![0u8; length as usize];
+    self.reader.read_exact(&mut payload)?;
+
+    Ok(Some(Record {
+      kind: kind,
+      dac: dac,
+      timestamp: timestamp,
+      payload: payload,
+    }))
+  }
+}
+
+/// Number of virtual DACs that appear in a capture file.
+pub fn dac_count(path: &str) -> Result<usize, EmulatorError> {
+  let mut reader = CaptureReader::open(path)?;
+  let mut count = 0;
+
+  while let Some(record) = reader.next_record()? {
+    count = count.max(record.dac as usize + 1);
+  }
+
+  Ok(count)
+}
+
+/// A duration in whole microseconds.
+#[inline]
+pub fn micros(duration: Duration) -> u64 {
+  duration.as_secs() * 1_000_000 + duration.subsec_nanos() as u64 / 1_000
+}
+
+/// A duration from whole microseconds.
+#[inline]
+pub fn from_micros(micros: u64) -> Duration {
+  Duration::new(micros / 1_000_000, (micros % 1_000_000) as u32 * 1_000)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use test_util::temp_path;
+
+  #[test]
+  fn test_capture_round_trip() {
+    let path = temp_path("test.capture");
+    let path = path.to_str().unwrap();
+
+    {
+      let recorder = Recorder::create(path).unwrap();
+      recorder.open(1, "10.0.0.2:5000".parse().unwrap()).unwrap();
+      recorder.command(1, &[0x70]).unwrap();
+      recorder.close(1).unwrap();
+    }
+
+    let mut reader = CaptureReader::open(path).unwrap();
+
+    let open = reader.next_record().unwrap().unwrap();
+    assert_eq!(open.kind, RecordKind::Open);
+    assert_eq!(open.dac, 1);
+    assert_eq!(open.payload, vec![10, 0, 0, 2, 0x88, 0x13]);
+
+    let command = reader.next_record().unwrap().unwrap();
+    assert_eq!(command.kind, RecordKind::Command);
+    assert_eq!(command.payload, vec![0x70]);
+    assert!(command.timestamp >= open.timestamp);
+
+    let close = reader.next_record().unwrap().unwrap();
+    assert_eq!(close.kind, RecordKind::Close);
+
+    assert!(reader.next_record().unwrap().is_none());
+
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -2,19 +2,38 @@
 
 use RuntimeOpts;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use capture::Recorder;
 use error::EmulatorError;
 use pipeline::Pipeline;
 use protocol::COMMAND_BEGIN;
+use protocol::COMMAND_CLEAR_ESTOP;
 use protocol::COMMAND_DATA;
+use protocol::COMMAND_ESTOP;
+use protocol::COMMAND_ESTOP_ALT;
+use protocol::COMMAND_PING;
 use protocol::COMMAND_PREPARE;
+use protocol::COMMAND_QUEUE_RATE_CHANGE;
+use protocol::COMMAND_STOP;
 use protocol::COMMAND_VERSION;
 use protocol::Command;
 use protocol::DacResponse;
 use protocol::DacStatus;
+use protocol::LIGHT_ENGINE_ESTOP;
+use protocol::LIGHT_ENGINE_FLAG_ESTOP_PACKET;
+use protocol::LIGHT_ENGINE_READY;
+use protocol::PLAYBACK_FLAG_ESTOP;
+use protocol::PLAYBACK_IDLE;
+use protocol::PLAYBACK_PLAYING;
+use protocol::PLAYBACK_PREPARED;
 use protocol::ResponseState;
+use protocol::SOURCE_NETWORK;
+use shutdown::Shutdown;
 use std::io::Cursor;
+use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
 use std::net::TcpListener;
 use std::net::TcpStream;
 use std::sync::Arc;
@@ -24,178 +43,208 @@ use std::time::Duration;
 /// Size of a single point in bytes.
 const POINT_SIZE : usize = 18;
 
+/// How often to check for shutdown while waiting for a client.
+const ACCEPT_POLL_MS : u64 = 50;
+
 /// Software version reported by the etherdream emulator.
 const VIRTUAL_DAC_VERSION: &'static str = "v0.0.1";
 
 /// Points sent from the Dac in a single DATA command payload.
+#[derive(Clone, Debug, PartialEq)]
 pub struct DacFrame {
   pub num_points: u16,
   pub point_data: Vec<u8>,
 }
 
+/// Network identity of a single virtual dac.
+#[derive(Clone,Debug)]
+pub struct DacConfig {
+  /// Position of the dac among those run by the process.
+  pub index: usize,
+  /// Local interface address to listen and broadcast on.
+  pub bind_address: Ipv4Addr,
+  /// TCP port to accept client connections on.
+  pub tcp_port: u16,
+  /// MAC address announced in broadcasts.
+  pub mac_address: [u8; 6],
+}
+
 pub struct Dac {
   /// Runtime arguments supplied to the program.
   opts: RuntimeOpts,
 
-  // TODO: Refactor so locking not required. Only a single thread needs this.
-  /// Runtime state of the virtual dac.
-  status: RwLock<DacStatus>,
+  /// Runtime state of the virtual dac, shared with the pipeline.
+  status: Arc<RwLock<DacStatus>>,
 
-  /// Point pipeline (point queue)
-  pipeline: Arc<Pipeline>,
+  /// Playback state machine, driven by the client's commands.
+  machine: StateMachine,
+
+  /// Socket accepting client connections.
+  listener: TcpListener,
+
+  /// Position of the dac among those run by the process.
+  index: usize,
+
+  /// Where to record the session, if anywhere.
+  recorder: Option<Arc<Recorder>>,
 }
 
 impl Dac {
-  pub fn new(opts: &RuntimeOpts, pipeline: Arc<Pipeline>) -> Dac {
-    Dac {
+  /// CTOR. Binds the listening socket immediately, so a port of zero picks
+  /// a free ephemeral port. See `local_addr`.
+  pub fn new(opts: &RuntimeOpts, config: &DacConfig,
+             status: Arc<RwLock<DacStatus>>, pipeline: Arc<Pipeline>)
+             -> Result<Dac, EmulatorError> {
+    let listener =
+        TcpListener::bind((config.bind_address, config.tcp_port))?;
+
+    // Polled, so that shutdown is noticed while waiting for a client.
+    listener.set_nonblocking(true)?;
+
+    Ok(Dac {
       opts: opts.clone(),
-      status: RwLock::new(DacStatus::empty()),
-      pipeline: pipeline,
-    }
+      status: status.clone(),
+      machine: StateMachine::new(status, pipeline),
+      listener: listener,
+      index: config.index,
+      recorder: None,
+    })
   }
 
-  /// Run the dac server. Accepts a connection, then begins the dac state
-  /// machine to handle points sent by the client.
-  pub fn run(&self) {
-    loop {
-      self.reset_status();
-      let _r = self.listen(); // TODO: handle errors.
+  /// Record every connection and command to a capture file.
+  pub fn set_recorder(&mut self, recorder: Arc<Recorder>) {
+    self.recorder = Some(recorder);
+  }
+
+  /// Address the dac accepts client connections on.
+  pub fn local_addr(&self) -> Result<SocketAddr, EmulatorError> {
+    Ok(self.listener.local_addr()?)
+  }
+
+  /// Run the dac server until shutdown. Accepts a connection, then begins
+  /// the dac state machine to handle points sent by the client.
+  pub fn run(&self, shutdown: &Shutdown) {
+    while !shutdown.is_stopped() {
+      self.machine.reset_status();
+      let _r = self.listen(shutdown); // TODO: handle errors.
     }
   }
 
-  pub fn listen(&self) -> Result<(), EmulatorError> {
-    let listener = TcpListener::bind("0.0.0.0:7765")?;
+  pub fn listen(&self, shutdown: &Shutdown) -> Result<(), EmulatorError> {
+    let (mut stream, peer) = loop {
+      match self.listener.accept() {
+        Ok(accepted) => break accepted,
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+          if shutdown.wait(Duration::from_millis(ACCEPT_POLL_MS)) {
+            return Err(EmulatorError::Stopped);
+          }
+        },
+        Err(e) => return Err(EmulatorError::from(e)),
+      }
+    };
 
-    let (mut stream, _socket_addr) = listener.accept()?;
+    stream.set_nonblocking(false)?;
     stream.set_read_timeout(Some(Duration::from_millis(100)))?;
     stream.set_write_timeout(Some(Duration::from_millis(100)))?;
 
     self.log("Connected!");
 
-    // Write info
-    self.write(&mut stream, &Command::Ping)?;
+    let pipeline = self.machine.pipeline();
+    let _r = pipeline.set_connected(true); // Only for display.
 
-    // TODO: Refactor into proper state machine.
-    loop {
-      // Read-write loop
-      let command = self.read_command(&mut stream)?;
+    if let Some(ref recorder) = self.recorder {
+      let _r = recorder.open(self.index as u8, peer); // Best effort.
+    }
 
-      self.log(&format!("Read command: {}", command));
+    let result = self.serve(&mut stream, shutdown);
 
-      match command {
-        Command::Begin { .. } => {
-          self.write(&mut stream, &command);
-        },
-        Command::Prepare => {
-          self.write(&mut stream, &command);
-        },
-        Command::Data { .. } => {
-          self.write(&mut stream, &command);
-        },
-        Command::Version => {
-          self.write_version(&mut stream);
-        },
-        _ => {
-          println!("Cannot send ack for unknown/unhandled command.");
-          return Err(EmulatorError::UnknownCommand);
-        },
-      }
+    let _r = pipeline.set_connected(false);
+
+    if let Some(ref recorder) = self.recorder {
+      let _r = recorder.close(self.index as u8); // Best effort.
     }
 
-    unreachable!()
+    result
   }
 
-  fn read_command(&self, stream: &mut TcpStream)
-      -> Result<Command, EmulatorError> {
-    let mut buf = [0u8; 2048]; // TODO: Better buffer size.
+  /// Handle commands from a connected client until it disconnects.
+  fn serve(&self, stream: &mut TcpStream, shutdown: &Shutdown)
+           -> Result<(), EmulatorError> {
+    // Write info
+    self.write(stream, ResponseState::Ack, &Command::Ping)?;
 
-    let size = stream.read(&mut buf)?;
+    let mut decoder = CommandDecoder::new();
 
-    match buf[0] {
-      COMMAND_DATA => {
-        let (num_points, point_data) = self.read_point_data(stream, buf, size)?;
-        let frame = DacFrame {
-          num_points: num_points,
-          point_data: point_data,
-        };
+    loop {
+      // Read-write loop
+      let command = self.read_command(stream, &mut decoder, shutdown)?;
 
-        // TODO: Handle full buffer.
-        let _r = self.pipeline.enqueue(frame);
+      self.log(&format!("Read command: {}", command));
 
-        // TODO: Report buffer size to apply back pressure.
-        match self.status.try_write() {
-          Err(_) => {},
-          Ok(mut status) => {
-            status.buffer_fullness = self.pipeline.queue_size()? as u16;
-          },
-        }
+      let response = match command {
+        Command::Version => {
+          self.write_version(stream)?;
+          ResponseState::Ack
+        },
+        _ => {
+          let response = self.handle_command(&command)?;
+          self.write(stream, response, &command)?;
+          response
+        },
+      };
 
-        Ok(Command::Data { num_points: num_points })
-      },
-      COMMAND_PREPARE => {
-        Ok(Command::Prepare)
-      },
-      COMMAND_BEGIN => {
-        // TODO: Include command code in error.
-        parse_begin(&buf).map_err(|_| EmulatorError::UnknownCommand)
-      },
-      COMMAND_VERSION => {
-        Ok(Command::Version)
-      },
-      _ => {
-        // TODO: Implement all commands
-        self.log("Read unknown");
-        Err(EmulatorError::UnknownCommand)
-      },
+      self.machine.pipeline().count_response(response)?;
     }
   }
 
-  // TODO: Simplify and clean up
-  /// Continue streaming point data payload.
-  /// Returns the number of points as well as the point bytes.
-  fn read_point_data(&self, stream: &mut TcpStream,
-                     buf: [u8; 2048],
-                     read_size: usize)
-                     -> Result<(u16, Vec<u8>), EmulatorError> {
-    let num_points = LittleEndian::read_u16(&buf[1 .. 3]);
-
-    self.log(&format!("Reading {} points.", num_points));
-
-    let points_size = POINT_SIZE * num_points as usize;
-    let total_size = points_size + 3usize; // 3 command header bytes
+  /// Apply a command to the playback state machine.
+  fn handle_command(&self, command: &Command)
+      -> Result<ResponseState, EmulatorError> {
+    self.machine.handle_command(command)
+  }
 
-    let mut already_read = read_size;
-    let mut point_buf : Vec<u8> = Vec::new();
+  /// Read from the client until the decoder has a complete command.
+  fn read_command(&self, stream: &mut TcpStream, decoder: &mut CommandDecoder,
+                  shutdown: &Shutdown) -> Result<Command, EmulatorError> {
+    loop {
+      if let Some(bytes) = decoder.next_frame()? {
+        if let Some(ref recorder) = self.recorder {
+          let _r = recorder.command(self.index as u8, &bytes); // Best effort.
+        }
+        return parse_command(&bytes);
+      }
 
-    point_buf.extend_from_slice(&buf[3.. read_size]); // Omit 3 header bytes
+      if shutdown.is_stopped() {
+        return Err(EmulatorError::Stopped);
+      }
 
-    while total_size > already_read {
-      let mut read_buf = [0u8; 2048];
+      let mut buf = [0u8; 2048];
 
-      let size = stream.read(&mut read_buf)?;
+      let size = match stream.read(&mut buf) {
+        Ok(size) => size,
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock
+            || e.kind() == ErrorKind::TimedOut => continue, // No data yet.
+        Err(e) => return Err(EmulatorError::from(e)),
+      };
 
       if size == 0 {
-        // NB: If the client disconnects now, we can get stuck in a loop reading
-        // zero bytes. Not sure why the socket doesn't report this error.
+        self.log("Client disconnected.");
         return Err(EmulatorError::ClientError);
       }
 
-      point_buf.extend_from_slice(&read_buf[0 .. size]);
-      already_read += size;
+      decoder.push(&buf[0 .. size]);
     }
-
-    Ok((num_points, point_buf))
   }
 
-  /// Write ACK back to client.
-  fn write(&self, stream: &mut TcpStream, command: &Command)
-      -> Result<(), EmulatorError> {
+  /// Write ACK/NAK response back to client.
+  fn write(&self, stream: &mut TcpStream, response: ResponseState,
+           command: &Command) -> Result<(), EmulatorError> {
     let status = self.status.read()?.clone();
 
     let response = &DacResponse::new(
-      ResponseState::Ack, command.value(), status).serialize();
+      response, command.value(), status).serialize();
 
-    let size = stream.write(response)?;
+    let _size = stream.write(response)?;
 
     Ok(())
   }
@@ -214,12 +263,6 @@ impl Dac {
     Ok(())
   }
 
-  /// Reset internal status.
-  fn reset_status(&self) {
-    let _r = self.status.try_write()
-        .map(|mut status| *status = DacStatus::empty()); // Ignore lock errors.
-  }
-
   fn log(&self, message: &str) {
     if self.opts.debug_protocol {
       // TODO: use logging crate or make a compile flag instead.
@@ -228,6 +271,166 @@ impl Dac {
   }
 }
 
+/// The playback state machine of a virtual dac. Commands from a connected
+/// client drive the dac's status and pipeline.
+pub struct StateMachine {
+  /// Runtime state of the virtual dac, shared with the pipeline.
+  status: Arc<RwLock<DacStatus>>,
+
+  /// Point pipeline (point queue)
+  pipeline: Arc<Pipeline>,
+}
+
+impl StateMachine {
+  /// CTOR.
+  pub fn new(status: Arc<RwLock<DacStatus>>, pipeline: Arc<Pipeline>)
+             -> StateMachine {
+    StateMachine {
+      status: status,
+      pipeline: pipeline,
+    }
+  }
+
+  /// Runtime state of the virtual dac.
+  pub fn status(&self) -> &Arc<RwLock<DacStatus>> {
+    &self.status
+  }
+
+  /// Point pipeline the state machine feeds.
+  pub fn pipeline(&self) -> &Arc<Pipeline> {
+    &self.pipeline
+  }
+
+  /// Apply a command to the playback state machine.
+  /// Returns the response that should be sent back to the client.
+  pub fn handle_command(&self, command: &Command)
+      -> Result<ResponseState, EmulatorError> {
+    let mut status = self.status.write()?;
+
+    match *command {
+      Command::Ping => {
+        return Ok(ResponseState::Ack);
+      },
+      Command::EmergencyStop => {
+        if status.playback_state != PLAYBACK_IDLE {
+          self.stop_playback(&mut status)?;
+          status.playback_flags |= PLAYBACK_FLAG_ESTOP;
+        }
+        status.light_engine_state = LIGHT_ENGINE_ESTOP;
+        status.light_engine_flags |= LIGHT_ENGINE_FLAG_ESTOP_PACKET;
+        return Ok(ResponseState::Ack);
+      },
+      Command::ClearEStop => {
+        // The emulator has no E-Stop input or thermal faults, so an E-Stop
+        // caused by the network can always be cleared.
+        status.light_engine_state = LIGHT_ENGINE_READY;
+        status.light_engine_flags = 0;
+        return Ok(ResponseState::Ack);
+      },
+      _ => {},
+    }
+
+    if status.light_engine_state == LIGHT_ENGINE_ESTOP {
+      return Ok(ResponseState::Stop);
+    }
+
+    // Playback belongs to another source, eg. ILDA files.
+    if status.source != SOURCE_NETWORK {
+      return Ok(ResponseState::InvalidCommand);
+    }
+
+    match *command {
+      Command::Prepare => {
+        if status.light_engine_state != LIGHT_ENGINE_READY
+            || status.playback_state != PLAYBACK_IDLE {
+          return Ok(ResponseState::InvalidCommand);
+        }
+
+        self.pipeline.clear()?;
+
+        status.playback_state = PLAYBACK_PREPARED;
+        status.playback_flags = 0;
+        status.buffer_fullness = 0;
+        status.point_count = 0;
+        Ok(ResponseState::Ack)
+      },
+      Command::QueueRateChange { point_rate } => {
+        if status.playback_state != PLAYBACK_PREPARED
+            && status.playback_state != PLAYBACK_PLAYING {
+          return Ok(ResponseState::InvalidCommand);
+        }
+
+        match self.pipeline.queue_rate_change(point_rate) {
+          Err(EmulatorError::PipelineFull) => Ok(ResponseState::BufferFull),
+          Err(e) => Err(e),
+          Ok(_) => Ok(ResponseState::Ack),
+        }
+      },
+      Command::Stop => {
+        if status.playback_state == PLAYBACK_IDLE {
+          return Ok(ResponseState::InvalidCommand);
+        }
+
+        self.stop_playback(&mut status)?;
+        Ok(ResponseState::Ack)
+      },
+      Command::Begin { point_rate, .. } => {
+        if status.playback_state != PLAYBACK_PREPARED
+            || self.pipeline.queue_size()? == 0 {
+          return Ok(ResponseState::InvalidCommand);
+        }
+
+        status.playback_state = PLAYBACK_PLAYING;
+        status.point_rate = point_rate;
+        Ok(ResponseState::Ack)
+      },
+      Command::Data { num_points, ref point_data } => {
+        if status.playback_state != PLAYBACK_PREPARED
+            && status.playback_state != PLAYBACK_PLAYING {
+          return Ok(ResponseState::InvalidCommand);
+        }
+
+        let frame = DacFrame {
+          num_points: num_points,
+          point_data: point_data.clone(),
+        };
+
+        let response = match self.pipeline.enqueue(frame) {
+          Err(EmulatorError::PipelineFull) => ResponseState::BufferFull,
+          Err(e) => return Err(e),
+          Ok(_) => ResponseState::Ack,
+        };
+
+        status.buffer_fullness = self.pipeline.queue_size()? as u16;
+        Ok(response)
+      },
+      _ => Ok(ResponseState::InvalidCommand),
+    }
+  }
+
+  /// Return the playback system to Idle, discarding any buffered points.
+  fn stop_playback(&self, status: &mut DacStatus)
+      -> Result<(), EmulatorError> {
+    self.pipeline.clear()?;
+
+    status.playback_state = PLAYBACK_IDLE;
+    status.buffer_fullness = 0;
+    status.point_rate = 0;
+    status.point_count = 0;
+    Ok(())
+  }
+
+  /// Reset internal status. Only network playback belongs to the client,
+  /// so playback from other sources carries on.
+  pub fn reset_status(&self) {
+    let _r = self.status.try_write().map(|mut status| {
+      if status.source == SOURCE_NETWORK {
+        *status = DacStatus::empty();
+      }
+    }); // Ignore lock errors.
+  }
+}
+
 /// Parse a 'begin' command.
 #[inline]
 pub fn parse_begin(bytes: &[u8]) -> Result<Command, EmulatorError> {
@@ -244,9 +447,135 @@ pub fn parse_begin(bytes: &[u8]) -> Result<Command, EmulatorError> {
   })
 }
 
+/// Parse a 'queue rate change' command.
+#[inline]
+pub fn parse_queue_rate_change(bytes: &[u8]) -> Result<Command, EmulatorError> {
+  let mut reader = Cursor::new(bytes);
+  let b = reader.read_u8()?;
+
+  if b != COMMAND_QUEUE_RATE_CHANGE {
+    return Err(EmulatorError::ParseError);
+  }
+
+  Ok(Command::QueueRateChange {
+    point_rate: reader.read_u32::<LittleEndian>()?,
+  })
+}
+
+/// Splits the client's byte stream into commands. TCP may deliver a single
+/// command across several reads, or several commands in a single read.
+pub struct CommandDecoder {
+  /// Bytes received but not yet decoded.
+  buf: Vec<u8>,
+}
+
+impl CommandDecoder {
+  /// CTOR.
+  pub fn new() -> CommandDecoder {
+    CommandDecoder {
+      buf: Vec::new(),
+    }
+  }
+
+  /// Add bytes read from the client.
+  pub fn push(&mut self, bytes: &[u8]) {
+    self.buf.extend_from_slice(bytes);
+  }
+
+  /// Decode the next command, if all of its bytes have arrived.
+  pub fn next_command(&mut self) -> Result<Option<Command>, EmulatorError> {
+    match self.next_frame()? {
+      None => Ok(None),
+      Some(bytes) => parse_command(&bytes).map(Some),
+    }
+  }
+
+  /// Take the raw bytes of the next command, if all of them have arrived.
+  pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, EmulatorError> {
+    let size = match command_size(&self.buf)? {
+      None => return Ok(None),
+      Some(size) => size,
+    };
+
+    if self.buf.len() < size {
+      return Ok(None);
+    }
+
+    Ok(Some(self.buf.drain(0 .. size).collect()))
+  }
+}
+
+/// Size in bytes of the command at the start of the buffer, or None if too
+/// few bytes have arrived to tell.
+fn command_size(bytes: &[u8]) -> Result<Option<usize>, EmulatorError> {
+  let size = match bytes.first() {
+    None => return Ok(None),
+    Some(&COMMAND_BEGIN) => 7, // u8 command, u16 low water mark, u32 rate
+    Some(&COMMAND_QUEUE_RATE_CHANGE) => 5, // u8 command, u32 rate
+    Some(&COMMAND_DATA) => {
+      if bytes.len() < 3 {
+        return Ok(None);
+      }
+      let num_points = LittleEndian::read_u16(&bytes[1 .. 3]);
+      3 + POINT_SIZE * num_points as usize // 3 command header bytes
+    },
+    Some(&COMMAND_CLEAR_ESTOP)
+      | Some(&COMMAND_ESTOP)
+      | Some(&COMMAND_ESTOP_ALT)
+      | Some(&COMMAND_PING)
+      | Some(&COMMAND_PREPARE)
+      | Some(&COMMAND_STOP)
+      | Some(&COMMAND_VERSION) => 1,
+    Some(_) => return Err(EmulatorError::UnknownCommand),
+  };
+  Ok(Some(size))
+}
+
+/// Parse a single, complete command.
+pub fn parse_command(bytes: &[u8]) -> Result<Command, EmulatorError> {
+  match bytes.first() {
+    Some(&COMMAND_BEGIN) => parse_begin(bytes),
+    Some(&COMMAND_CLEAR_ESTOP) => Ok(Command::ClearEStop),
+    Some(&COMMAND_DATA) => parse_data(bytes),
+    Some(&COMMAND_ESTOP) | Some(&COMMAND_ESTOP_ALT) =>
+        Ok(Command::EmergencyStop),
+    Some(&COMMAND_PING) => Ok(Command::Ping),
+    Some(&COMMAND_PREPARE) => Ok(Command::Prepare),
+    Some(&COMMAND_QUEUE_RATE_CHANGE) => parse_queue_rate_change(bytes),
+    Some(&COMMAND_STOP) => Ok(Command::Stop),
+    Some(&COMMAND_VERSION) => Ok(Command::Version),
+    Some(_) => Err(EmulatorError::UnknownCommand),
+    None => Err(EmulatorError::ParseError),
+  }
+}
+
+/// Parse a 'data' command.
+#[inline]
+pub fn parse_data(bytes: &[u8]) -> Result<Command, EmulatorError> {
+  let mut reader = Cursor::new(bytes);
+  let b = reader.read_u8()?;
+
+  if b != COMMAND_DATA {
+    return Err(EmulatorError::ParseError);
+  }
+
+  let num_points = reader.read_u16::<LittleEndian>()?;
+  let points_size = POINT_SIZE * num_points as usize;
+
+  if bytes.len() < 3 + points_size {
+    return Err(EmulatorError::ParseError);
+  }
+
+  Ok(Command::Data {
+    num_points: num_points,
+    point_data: bytes[3 .. 3 + points_size].to_vec(),
+  })
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use protocol::SOURCE_ILDA;
   use std::thread;
   use std::sync::Arc;
 
@@ -254,10 +583,14 @@ mod tests {
 
   #[test]
   fn test_version_command() {
-    thread::spawn(move || make_dac().run());
-    thread::sleep_ms(250); // Wait for DAC to accept connections.
+    let dac = make_dac();
+    let addr = dac.local_addr().unwrap();
+    let shutdown = Shutdown::new();
+    let shutdown2 = shutdown.clone();
 
-    let mut stream = TcpStream::connect("127.0.0.1:7765").unwrap();
+    let dac_thread = thread::spawn(move || dac.run(&shutdown2));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
 
     assert_ack(&mut stream, 0x3f); // '?' ping
 
@@ -278,6 +611,64 @@ mod tests {
     ];
 
     assert_eq!(resp, expected.as_ref());
+
+    shutdown.stop();
+    dac_thread.join().unwrap();
+  }
+
+  #[test]
+  fn test_data_requires_prepare() {
+    let dac = make_dac();
+
+    assert_eq!(dac.handle_command(&data_command(1)).unwrap(),
+               ResponseState::InvalidCommand);
+    assert_eq!(dac.handle_command(&Command::Prepare).unwrap(),
+               ResponseState::Ack);
+    assert_eq!(dac.handle_command(&data_command(1)).unwrap(),
+               ResponseState::Ack);
+  }
+
+  #[test]
+  fn test_data_buffer_full() {
+    let dac = make_dac();
+
+    dac.handle_command(&Command::Prepare).unwrap();
+
+    assert_eq!(dac.handle_command(&data_command(1_800)).unwrap(),
+               ResponseState::Ack);
+    assert_eq!(dac.handle_command(&data_command(1)).unwrap(),
+               ResponseState::BufferFull);
+    assert_eq!(dac.handle_command(&data_command(0)).unwrap(),
+               ResponseState::Ack);
+
+    let status = dac.status.read().unwrap().clone();
+    assert_eq!(status.buffer_fullness, 1_800);
+  }
+
+  #[test]
+  fn test_playback_state_transitions() {
+    let dac = make_dac();
+    let begin = Command::Begin { low_water_mark: 0, point_rate: 30_000 };
+
+    // Cannot begin while idle, or while prepared with an empty buffer.
+    assert_eq!(dac.handle_command(&begin).unwrap(),
+               ResponseState::InvalidCommand);
+    assert_eq!(dac.handle_command(&Command::Prepare).unwrap(),
+               ResponseState::Ack);
+    assert_eq!(dac.handle_command(&begin).unwrap(),
+               ResponseState::InvalidCommand);
+
+    dac.handle_command(&data_command(10)).unwrap();
+
+    assert_eq!(dac.handle_command(&begin).unwrap(), ResponseState::Ack);
+
+    let status = dac.status.read().unwrap().clone();
+    assert_eq!(status.playback_state, PLAYBACK_PLAYING);
+    assert_eq!(status.point_rate, 30_000);
+
+    // Prepare is only valid from idle.
+    assert_eq!(dac.handle_command(&Command::Prepare).unwrap(),
+               ResponseState::InvalidCommand);
   }
 
   // Assert a command was read by the DAC and ack'd
@@ -295,9 +686,165 @@ mod tests {
     assert_eq!(buf, expected.as_ref());
   }
 
+  #[test]
+  fn test_stop_returns_to_idle() {
+    let dac = make_dac();
+
+    assert_eq!(dac.handle_command(&Command::Stop).unwrap(),
+               ResponseState::InvalidCommand);
+
+    dac.handle_command(&Command::Prepare).unwrap();
+    dac.handle_command(&data_command(10)).unwrap();
+
+    assert_eq!(dac.handle_command(&Command::Stop).unwrap(),
+               ResponseState::Ack);
+
+    let status = dac.status.read().unwrap().clone();
+    assert_eq!(status.playback_state, PLAYBACK_IDLE);
+    assert_eq!(status.buffer_fullness, 0);
+  }
+
+  #[test]
+  fn test_emergency_stop() {
+    let dac = make_dac();
+
+    dac.handle_command(&Command::Prepare).unwrap();
+
+    assert_eq!(dac.handle_command(&Command::EmergencyStop).unwrap(),
+               ResponseState::Ack);
+
+    let status = dac.status.read().unwrap().clone();
+    assert_eq!(status.light_engine_state, LIGHT_ENGINE_ESTOP);
+    assert_eq!(status.light_engine_flags, LIGHT_ENGINE_FLAG_ESTOP_PACKET);
+    assert_eq!(status.playback_state, PLAYBACK_IDLE);
+    assert_eq!(status.playback_flags, PLAYBACK_FLAG_ESTOP);
+
+    // NAK - Stop Condition until the E-Stop is cleared.
+    assert_eq!(dac.handle_command(&Command::Prepare).unwrap(),
+               ResponseState::Stop);
+    assert_eq!(dac.handle_command(&Command::ClearEStop).unwrap(),
+               ResponseState::Ack);
+    assert_eq!(dac.handle_command(&Command::Prepare).unwrap(),
+               ResponseState::Ack);
+
+    let status = dac.status.read().unwrap().clone();
+    assert_eq!(status.light_engine_state, LIGHT_ENGINE_READY);
+    assert_eq!(status.light_engine_flags, 0);
+    assert_eq!(status.playback_flags, 0);
+  }
+
+  #[test]
+  fn test_other_source_owns_playback() {
+    let dac = make_dac();
+    dac.status.write().unwrap().source = SOURCE_ILDA;
+
+    assert_eq!(dac.handle_command(&Command::Ping).unwrap(),
+               ResponseState::Ack);
+    assert_eq!(dac.handle_command(&Command::Prepare).unwrap(),
+               ResponseState::InvalidCommand);
+
+    // Clients coming and going do not interrupt the source.
+    dac.machine.reset_status();
+    assert_eq!(dac.status.read().unwrap().source, SOURCE_ILDA);
+  }
+
+  #[test]
+  fn test_queue_rate_change() {
+    let dac = make_dac();
+    let queue = Command::QueueRateChange { point_rate: 20_000 };
+
+    assert_eq!(dac.handle_command(&queue).unwrap(),
+               ResponseState::InvalidCommand);
+
+    dac.handle_command(&Command::Prepare).unwrap();
+
+    for _i in 0 .. 8 {
+      assert_eq!(dac.handle_command(&queue).unwrap(), ResponseState::Ack);
+    }
+
+    // The rate change buffer is now full.
+    assert_eq!(dac.handle_command(&queue).unwrap(),
+               ResponseState::BufferFull);
+  }
+
+  #[test]
+  fn test_decode_coalesced_commands() {
+    let mut decoder = CommandDecoder::new();
+
+    // Data with two points, immediately followed by Begin and Ping.
+    let mut bytes = vec![0x64, 0x02, 0x00];
+    bytes.extend_from_slice(&[0x11; POINT_SIZE * 2]);
+    bytes.extend_from_slice(&[0x62, 0x00, 0x00, 0x30, 0x75, 0x00, 0x00]);
+    bytes.push(0x3f);
+
+    decoder.push(&bytes);
+
+    match decoder.next_command().unwrap() {
+      Some(Command::Data { num_points, ref point_data }) => {
+        assert_eq!(num_points, 2);
+        assert_eq!(point_data, &vec![0x11; POINT_SIZE * 2]);
+      },
+      _ => panic!("Expected Data."),
+    }
+
+    match decoder.next_command().unwrap() {
+      Some(Command::Begin { point_rate, .. }) => assert_eq!(point_rate, 30_000),
+      _ => panic!("Expected Begin."),
+    }
+
+    match decoder.next_command().unwrap() {
+      Some(Command::Ping) => {},
+      _ => panic!("Expected Ping."),
+    }
+
+    assert!(decoder.next_command().unwrap().is_none());
+  }
+
+  #[test]
+  fn test_decode_byte_by_byte() {
+    let mut decoder = CommandDecoder::new();
+
+    let mut bytes = vec![0x62, 0x00, 0x00, 0x30, 0x75, 0x00, 0x00];
+    bytes.extend_from_slice(&[0x64, 0x01, 0x00]);
+    bytes.extend_from_slice(&[0x22; POINT_SIZE]);
+    bytes.extend_from_slice(&[0x71, 0x20, 0x4e, 0x00, 0x00]);
+
+    let mut commands = Vec::new();
+
+    for byte in bytes {
+      decoder.push(&[byte]);
+      if let Some(command) = decoder.next_command().unwrap() {
+        commands.push(command.value());
+      }
+    }
+
+    assert_eq!(commands, vec![0x62, 0x64, 0x71]);
+  }
+
+  #[test]
+  fn test_decode_unknown_command() {
+    let mut decoder = CommandDecoder::new();
+    decoder.push(&[0x7a]);
+    assert!(decoder.next_command().is_err());
+  }
+
+  fn data_command(num_points: u16) -> Command {
+    Command::Data {
+      num_points: num_points,
+      point_data: vec![0u8; POINT_SIZE * num_points as usize],
+    }
+  }
+
   fn make_dac() -> Dac {
-    let opts = RuntimeOpts { debug_protocol: false, headless: true };
-    let pipeline = Pipeline::new();
-    Dac::new(&opts, Arc::new(pipeline))
+    let opts = RuntimeOpts {
+      headless: true,
+      bind_addresses: vec![Ipv4Addr::new(127, 0, 0, 1)],
+      tcp_port: 0, // Ephemeral, so tests can run in parallel.
+      ..RuntimeOpts::default()
+    };
+    let config = opts.dac_configs().unwrap().remove(0);
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Pipeline::new(status.clone(), 1_800);
+    Dac::new(&opts, &config, status, Arc::new(pipeline)).unwrap()
   }
 }
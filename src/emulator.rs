@@ -0,0 +1,218 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+use RuntimeOpts;
+use broadcast::broadcast_thread;
+use dac::Dac;
+use error::EmulatorError;
+use pipeline::Pipeline;
+use protocol::DacStatus;
+use protocol::Point;
+use shutdown::Shutdown;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread::JoinHandle;
+use std::thread;
+
+/// Builds a virtual DAC that runs on background threads, for embedding the
+/// emulator in other programs and test suites.
+///
+/// By default the DAC listens on an ephemeral port on localhost and does not
+/// send UDP broadcasts, so many can run side by side.
+pub struct EmulatorBuilder {
+  opts: RuntimeOpts,
+  broadcast: bool,
+}
+
+impl EmulatorBuilder {
+  /// CTOR.
+  pub fn new() -> EmulatorBuilder {
+    EmulatorBuilder {
+      opts: RuntimeOpts {
+        headless: true,
+        bind_addresses: vec![Ipv4Addr::new(127, 0, 0, 1)],
+        tcp_port: 0,
+        ..RuntimeOpts::default()
+      },
+      broadcast: false,
+    }
+  }
+
+  /// Local interface address to listen on.
+  pub fn bind_address(mut self, bind_address: Ipv4Addr) -> EmulatorBuilder {
+    self.opts.bind_addresses = vec![bind_address];
+    self
+  }
+
+  /// TCP port to listen on. Zero picks a free port.
+  pub fn tcp_port(mut self, tcp_port: u16) -> EmulatorBuilder {
+    self.opts.tcp_port = tcp_port;
+    self
+  }
+
+  /// Number of points the DAC can buffer.
+  pub fn buffer_capacity(mut self, buffer_capacity: u16) -> EmulatorBuilder {
+    self.opts.buffer_capacity = buffer_capacity;
+    self
+  }
+
+  /// MAC address announced in broadcasts.
+  pub fn mac_address(mut self, mac_address: [u8; 6]) -> EmulatorBuilder {
+    self.opts.mac_address = mac_address;
+    self
+  }
+
+  /// Whether to announce the DAC with UDP broadcasts.
+  pub fn broadcast(mut self, broadcast: bool) -> EmulatorBuilder {
+    self.broadcast = broadcast;
+    self
+  }
+
+  /// Print each message in the client/server protocol, and playback events
+  /// such as underflows, to STDOUT.
+  pub fn debug_protocol(mut self, debug_protocol: bool) -> EmulatorBuilder {
+    self.opts.debug_protocol = debug_protocol;
+    self
+  }
+
+  /// Bind the DAC and start its threads.
+  pub fn start(self) -> Result<EmulatorHandle, EmulatorError> {
+    let opts = self.opts;
+    let config = opts.dac_configs()?.remove(0);
+
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Arc::new(Pipeline::new(status.clone(),
+                                          opts.buffer_capacity));
+    pipeline.set_debug(opts.debug_protocol);
+
+    let dac = Dac::new(&opts, &config, status.clone(), pipeline.clone())?;
+    let local_addr = dac.local_addr()?;
+
+    let shutdown = Shutdown::new();
+    let mut threads = Vec::new();
+
+    let shutdown2 = shutdown.clone();
+    threads.push(thread::spawn(move || dac.run(&shutdown2)));
+
+    let shutdown2 = shutdown.clone();
+    let pipeline2 = pipeline.clone();
+    threads.push(thread::spawn(move || pipeline2.process(&shutdown2)));
+
+    if self.broadcast {
+      let shutdown2 = shutdown.clone();
+      let status2 = status.clone();
+      threads.push(thread::spawn(move || {
+        broadcast_thread(&opts, &config, status2, &shutdown2)
+      }));
+    }
+
+    Ok(EmulatorHandle {
+      local_addr: local_addr,
+      status: status,
+      pipeline: pipeline,
+      shutdown: shutdown,
+      threads: threads,
+    })
+  }
+}
+
+/// A running virtual DAC. Dropping the handle shuts the DAC down.
+pub struct EmulatorHandle {
+  local_addr: SocketAddr,
+  status: Arc<RwLock<DacStatus>>,
+  pipeline: Arc<Pipeline>,
+  shutdown: Shutdown,
+  threads: Vec<JoinHandle<()>>,
+}
+
+impl EmulatorHandle {
+  /// Address clients should connect to.
+  pub fn local_addr(&self) -> SocketAddr {
+    self.local_addr
+  }
+
+  /// TCP port clients should connect to.
+  pub fn port(&self) -> u16 {
+    self.local_addr.port()
+  }
+
+  /// Current state of the DAC.
+  pub fn status(&self) -> Result<DacStatus, EmulatorError> {
+    Ok(self.status.read()?.clone())
+  }
+
+  /// Points played since the last call, oldest first. Only the most recent
+  /// points are kept if this isn't called often enough.
+  pub fn take_points(&self) -> Result<Vec<Point>, EmulatorError> {
+    self.pipeline.dequeue()
+  }
+
+  /// Stop the DAC and wait for its threads to finish.
+  pub fn shutdown(mut self) {
+    self.stop();
+  }
+
+  fn stop(&mut self) {
+    self.shutdown.stop();
+    for thread in self.threads.drain(..) {
+      let _r = thread.join();
+    }
+  }
+}
+
+impl Drop for EmulatorHandle {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use protocol::PLAYBACK_FLAG_UNDERFLOW;
+  use protocol::PLAYBACK_IDLE;
+  use std::io::Read;
+  use std::io::Write;
+  use std::net::TcpStream;
+  use std::time::Duration;
+
+  #[test]
+  fn test_embedded_playback() {
+    let emulator = EmulatorBuilder::new().start().unwrap();
+    assert!(emulator.port() != 0);
+
+    let mut stream = TcpStream::connect(emulator.local_addr()).unwrap();
+    read_response(&mut stream); // Initial status.
+
+    // Prepare, one point at x = 0x0102, then begin at 10kpps.
+    let mut bytes = vec![0x70, 0x64, 0x01, 0x00, 0x00, 0x00, 0x02, 0x01];
+    bytes.extend_from_slice(&[0u8; 14]);
+    bytes.extend_from_slice(&[0x62, 0x00, 0x00, 0x10, 0x27, 0x00, 0x00]);
+    stream.write_all(&bytes).unwrap();
+
+    for command in vec![0x70, 0x64, 0x62] {
+      let response = read_response(&mut stream);
+      assert_eq!(&response[0 .. 2], &[0x61, command]);
+    }
+
+    thread::sleep(Duration::from_millis(50));
+
+    let points = emulator.take_points().unwrap();
+    assert_eq!(points.len(), 1);
+    assert_eq!(points[0].x, 0x0102);
+
+    // The single point is long gone, so playback has underflowed.
+    let status = emulator.status().unwrap();
+    assert_eq!(status.playback_state, PLAYBACK_IDLE);
+    assert_eq!(status.playback_flags, PLAYBACK_FLAG_UNDERFLOW);
+
+    emulator.shutdown();
+  }
+
+  fn read_response(stream: &mut TcpStream) -> [u8; 22] {
+    let mut buf = [0u8; 22];
+    stream.read_exact(&mut buf).unwrap();
+    buf
+  }
+}
@@ -15,10 +15,14 @@ pub enum EmulatorError {
   IoError { cause: io::Error },
   /// An issue obtaining a std::sync lock. Should not occur.
   LockError,
+  /// Options that can't be satisfied, eg. DAC ports past 65535.
+  InvalidConfig,
   /// Error parsing client request.
   ParseError,
   /// Cannot put anything else on the point pipeline.
   PipelineFull,
+  /// The emulator is shutting down.
+  Stopped,
   /// Unknown command received from the client. Some client commands are valid,
   /// but we do not yet support them.
   UnknownCommand,
@@ -31,9 +35,11 @@ impl fmt::Display for EmulatorError {
         return write!(f, "IoError {}", cause);
       },
       EmulatorError::ClientError => "ClientError",
+      EmulatorError::InvalidConfig => "InvalidConfig",
       EmulatorError::LockError => "LockError",
       EmulatorError::ParseError => "ParseError",
       EmulatorError::PipelineFull => "PipelineFull",
+      EmulatorError::Stopped => "Stopped",
       EmulatorError::UnknownCommand => "UnknownCommand",
     };
     write!(f, "{}", error_detail)
@@ -45,9 +51,11 @@ impl error::Error for EmulatorError {
     match *self {
       EmulatorError::ClientError => "ClientError",
       EmulatorError::IoError { .. } => "IoError",
+      EmulatorError::InvalidConfig => "InvalidConfig",
       EmulatorError::LockError => "LockError",
       EmulatorError::ParseError => "ParseError",
       EmulatorError::PipelineFull => "PipelineFull",
+      EmulatorError::Stopped => "Stopped",
       EmulatorError::UnknownCommand => "UnknownCommand",
     }
   }
@@ -0,0 +1,383 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Exports played points as ILDA true color (format 4 or 5) animations, so
+//! content can be handed to other laser software.
+//!
+//! Clients send a continuous stream of points rather than frames, so the
+//! stream is cut into frames either where the scan starts repeating itself,
+//! or at fixed intervals of playback time.
+
+use byteorder::BigEndian;
+use byteorder::WriteBytesExt;
+use error::EmulatorError;
+use protocol::Point;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use std::time::Duration;
+
+/// Number of points that must match the start of a frame before it is
+/// considered to be repeating.
+const LOOP_MATCH_POINTS : usize = 16;
+
+/// Most points (and frames) an ILDA file can describe.
+const ILDA_LIMIT : usize = 65_535;
+
+/// ILDA status code bits.
+const STATUS_LAST_POINT : u8 = 1 << 7;
+const STATUS_BLANKED : u8 = 1 << 6;
+
+/// Offset of the total frames field in an ILDA header.
+const TOTAL_FRAMES_OFFSET : u64 = 28;
+
+/// ILDA true color formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IldaFormat {
+  /// Format 4. Z is always zero.
+  TrueColor3d,
+  /// Format 5.
+  TrueColor2d,
+}
+
+impl IldaFormat {
+  /// The format with the given ILDA format code, if supported.
+  pub fn from_code(code: u8) -> Option<IldaFormat> {
+    match code {
+      4 => Some(IldaFormat::TrueColor3d),
+      5 => Some(IldaFormat::TrueColor2d),
+      _ => None,
+    }
+  }
+
+  fn code(&self) -> u8 {
+    match *self {
+      IldaFormat::TrueColor3d => 4,
+      IldaFormat::TrueColor2d => 5,
+    }
+  }
+}
+
+/// How the point stream is cut into frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segmentation {
+  /// A frame ends where the points start repeating it.
+  Loop,
+  /// A frame ends after this much playback time.
+  Window(Duration),
+}
+
+/// Writes frames to an ILDA file.
+pub struct IldaWriter {
+  file: BufWriter<File>,
+  format: IldaFormat,
+  /// Where the header of each frame starts, to fill in the frame total.
+  headers: Vec<u64>,
+  position: u64,
+}
+
+impl IldaWriter {
+  /// Create (or truncate) an ILDA file.
+  pub fn create(path: &str, format: IldaFormat)
+                -> Result<IldaWriter, EmulatorError> {
+    Ok(IldaWriter {
+      file: BufWriter::new(File::create(path)?),
+      format: format,
+      headers: Vec::new(),
+      position: 0,
+    })
+  }
+
+  /// Number of frames written.
+  pub fn frames(&self) -> usize {
+    self.headers.len()
+  }
+
+  /// Write a frame. Frames past the ILDA limits are dropped.
+  pub fn write_frame(&mut self, points: &[Point])
+                     -> Result<(), EmulatorError> {
+    if points.is_empty() || self.headers.len() >= ILDA_LIMIT {
+      return Ok(());
+    }
+
+    let points = &points[.. points.len().min(ILDA_LIMIT)];
+    let number = self.headers.len() as u16;
+
+    self.headers.push(self.position);
+    self.write_header(points.len() as u16, number)?;
+
+    for (i, point) in points.iter().enumerate() {
+      let mut status = 0;
+      if i == points.len() - 1 {
+        status |= STATUS_LAST_POINT;
+      }
+      if point.r == 0 && point.g == 0 && point.b == 0 {
+        status |= STATUS_BLANKED;
+      }
+
+      self.file.write_i16::<BigEndian>(point.x)?;
+      self.file.write_i16::<BigEndian>(point.y)?;
+      self.position += 4;
+
+      if self.format == IldaFormat::TrueColor3d {
+        self.file.write_i16::<BigEndian>(0)?;
+        self.position += 2;
+      }
+
+      self.file.write_all(&[status, (point.b >> 8) as u8,
+          (point.g >> 8) as u8, (point.r >> 8) as u8])?;
+      self.position += 4;
+    }
+
+    Ok(())
+  }
+
+  /// Write the end of file header and fill in the frame total of each frame.
+  pub fn finish(&mut self) -> Result<(), EmulatorError> {
+    let total = self.headers.len() as u16;
+    self.write_header(0, 0)?;
+
+    for offset in self.headers.iter() {
+      self.file.seek(SeekFrom::Start(offset + TOTAL_FRAMES_OFFSET))?;
+      self.file.write_u16::<BigEndian>(total)?;
+    }
+
+    self.file.seek(SeekFrom::End(0))?;
+    self.file.flush()?;
+    Ok(())
+  }
+
+  fn write_header(&mut self, record_count: u16, number: u16)
+                  -> Result<(), EmulatorError> {
+    self.file.write_all(b"ILDA")?;
+    self.file.write_all(&[0, 0, 0, self.format.code()])?;
+    self.file.write_all(&[0u8; 8])?; // Frame name
+    self.file.write_all(b"EtherDrm")?; // Company name
+    self.file.write_u16::<BigEndian>(record_count)?;
+    self.file.write_u16::<BigEndian>(number)?;
+    self.file.write_u16::<BigEndian>(0)?; // Total frames, filled in later.
+    self.file.write_all(&[0, 0])?; // Projector, reserved
+    self.position += 32;
+    Ok(())
+  }
+}
+
+/// Cuts played points into frames and writes them to an ILDA file. The file
+/// is finished when the exporter is dropped.
+pub struct IldaExporter {
+  writer: IldaWriter,
+  segmentation: Segmentation,
+  /// Points of the frame in progress.
+  frame: Vec<Point>,
+  /// Points that match the start of the frame so far, if it may be
+  /// repeating.
+  repeat: Vec<Point>,
+  /// Playback time of the frame in progress, in seconds.
+  elapsed: f64,
+}
+
+impl IldaExporter {
+  /// CTOR.
+  pub fn create(path: &str, format: IldaFormat, segmentation: Segmentation)
+                -> Result<IldaExporter, EmulatorError> {
+    Ok(IldaExporter {
+      writer: IldaWriter::create(path, format)?,
+      segmentation: segmentation,
+      frame: Vec::new(),
+      repeat: Vec::new(),
+      elapsed: 0.0,
+    })
+  }
+
+  /// Number of frames written so far.
+  pub fn frames(&self) -> usize {
+    self.writer.frames()
+  }
+
+  /// Add a point played at the given point rate.
+  pub fn push(&mut self, point: Point, point_rate: u32)
+              -> Result<(), EmulatorError> {
+    match self.segmentation {
+      Segmentation::Loop => self.push_loop(point)?,
+      Segmentation::Window(window) => {
+        self.frame.push(point);
+        self.elapsed += 1.0 / point_rate.max(1) as f64;
+
+        let window = window.as_secs() as f64
+            + window.subsec_nanos() as f64 / 1e9;
+
+        if self.elapsed >= window {
+          self.end_frame(Vec::new())?;
+        }
+      },
+    }
+
+    if self.frame.len() + self.repeat.len() >= ILDA_LIMIT {
+      let mut frame = mem::replace(&mut self.repeat, Vec::new());
+      self.frame.append(&mut frame);
+      self.end_frame(Vec::new())?;
+    }
+
+    Ok(())
+  }
+
+  fn push_loop(&mut self, point: Point) -> Result<(), EmulatorError> {
+    if !self.repeat.is_empty() {
+      if point == self.frame[self.repeat.len()] {
+        self.repeat.push(point);
+
+        if self.repeat.len() >= LOOP_MATCH_POINTS {
+          // The repeated points start the next frame.
+          let next = mem::replace(&mut self.repeat, Vec::new());
+          self.end_frame(next)?;
+        }
+        return Ok(());
+      }
+
+      // Not a repeat after all.
+      let mut repeat = mem::replace(&mut self.repeat, Vec::new());
+      self.frame.append(&mut repeat);
+    }
+
+    if self.frame.len() > LOOP_MATCH_POINTS && point == self.frame[0] {
+      self.repeat.push(point);
+    } else {
+      self.frame.push(point);
+    }
+
+    Ok(())
+  }
+
+  /// Write the frame in progress and start the next one.
+  fn end_frame(&mut self, next: Vec<Point>) -> Result<(), EmulatorError> {
+    let frame = mem::replace(&mut self.frame, next);
+    self.elapsed = 0.0;
+    self.writer.write_frame(&frame)
+  }
+
+  fn finish(&mut self) -> Result<(), EmulatorError> {
+    let mut repeat = mem::replace(&mut self.repeat, Vec::new());
+    self.frame.append(&mut repeat);
+    self.end_frame(Vec::new())?;
+    self.writer.finish()
+  }
+}
+
+impl Drop for IldaExporter {
+  fn drop(&mut self) {
+    let _r = self.finish(); // Nowhere to report errors.
+  }
+}
+
+/// Name of the export file for a DAC. With several DACs, each gets its own
+/// file, numbered before the extension.
+pub fn dac_path(path: &str, index: usize, num_dacs: usize) -> String {
+  if num_dacs <= 1 {
+    return path.to_string();
+  }
+
+  let path = Path::new(path);
+  let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+  let name = match path.extension().and_then(|s| s.to_str()) {
+    None => format!("{}-{}", stem, index),
+    Some(extension) => format!("{}-{}.{}", stem, index, extension),
+  };
+
+  path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ilda::data::IldaEntry;
+  use ilda::parser;
+  use std::fs;
+  use test_util::temp_path;
+
+  #[test]
+  fn test_export_loops() {
+    let path = temp_path("loops.ild");
+    let path = path.to_str().unwrap();
+
+    {
+      let mut exporter = IldaExporter::create(path, IldaFormat::TrueColor2d,
+                                              Segmentation::Loop).unwrap();
+
+      // Three scans of a 100 point frame.
+      for _scan in 0 .. 3 {
+        for i in 0 .. 100 {
+          exporter.push(make_point(i), 30_000).unwrap();
+        }
+      }
+
+      assert_eq!(exporter.frames(), 2);
+    }
+
+    let entries = parser::read_file(path).ok().unwrap();
+    let headers : Vec<(u16, u16, u16)> = entries.iter()
+        .filter_map(|entry| match *entry {
+          IldaEntry::HeaderEntry(ref h) =>
+            Some((h.record_count, h.number, h.total_frames)),
+          _ => None,
+        })
+        .collect();
+
+    // The end of file header is last.
+    assert_eq!(headers,
+               vec![(100, 0, 3), (100, 1, 3), (100, 2, 3), (0, 0, 0)]);
+
+    match entries[2] {
+      IldaEntry::TcPoint2dEntry(ref point) => {
+        assert_eq!(point.x, 1);
+        assert_eq!(point.r, 255);
+        assert_eq!(point.status_code, 0);
+      },
+      _ => panic!("Expected a format 5 point."),
+    }
+
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_export_windows() {
+    let path = temp_path("windows.ild");
+    let path = path.to_str().unwrap();
+
+    let mut exporter = IldaExporter::create(path, IldaFormat::TrueColor3d,
+        Segmentation::Window(Duration::from_millis(10))).unwrap();
+
+    // 25ms of points at 10kpps.
+    for i in 0 .. 250 {
+      exporter.push(make_point(i), 10_000).unwrap();
+    }
+
+    assert_eq!(exporter.frames(), 2);
+
+    drop(exporter);
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_dac_path() {
+    assert_eq!(dac_path("show.ild", 1, 1), "show.ild");
+    assert_eq!(dac_path("out/show.ild", 1, 2), "out/show-1.ild");
+    assert_eq!(dac_path("show", 0, 2), "show-0");
+  }
+
+  fn make_point(i: i16) -> Point {
+    Point {
+      control: 0,
+      x: i,
+      y: -i,
+      i: 0,
+      r: 65535,
+      g: 0,
+      b: 0,
+      u1: 0,
+      u2: 0,
+    }
+  }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! A simple stroke font for labels, drawn with lines like a laser would.
+//! Needs no font files, and draws the same in the window and offscreen.
+
+use graphics::Graphics;
+use graphics::Line;
+use graphics::draw_state::DrawState;
+use graphics::math::Matrix2d;
+
+/// Glyphs are drawn on a grid this many units wide and high, with y
+/// pointing down. Some descend a unit below.
+const GLYPH_WIDTH : f64 = 4.0;
+const GLYPH_HEIGHT : f64 = 6.0;
+
+/// Space between glyphs, in grid units.
+const GLYPH_SPACING : f64 = 2.0;
+
+type Strokes = &'static [&'static [(u8, u8)]];
+
+/// Strokes of each glyph, as polylines on the glyph grid. Lowercase letters
+/// are drawn as uppercase.
+static GLYPHS : [(char, Strokes); 53] = [
+  (' ', &[]),
+  ('0', &[&[(0, 0), (4, 0), (4, 6), (0, 6), (0, 0)], &[(4, 0), (0, 6)]]),
+  ('1', &[&[(1, 1), (2, 0), (2, 6)], &[(1, 6), (3, 6)]]),
+  ('2', &[&[(0, 0), (4, 0), (4, 3), (0, 3), (0, 6), (4, 6)]]),
+  ('3', &[&[(0, 0), (4, 0), (4, 6), (0, 6)], &[(1, 3), (4, 3)]]),
+  ('4', &[&[(0, 0), (0, 3), (4, 3)], &[(4, 0), (4, 6)]]),
+  ('5', &[&[(4, 0), (0, 0), (0, 3), (4, 3), (4, 6), (0, 6)]]),
+  ('6', &[&[(4, 0), (0, 0), (0, 6), (4, 6), (4, 3), (0, 3)]]),
+  ('7', &[&[(0, 0), (4, 0), (4, 6)]]),
+  ('8', &[&[(0, 0), (4, 0), (4, 6), (0, 6), (0, 0)], &[(0, 3), (4, 3)]]),
+  ('9', &[&[(4, 3), (0, 3), (0, 0), (4, 0), (4, 6), (0, 6)]]),
+  ('A', &[&[(0, 6), (0, 0), (4, 0), (4, 6)], &[(0, 3), (4, 3)]]),
+  ('B', &[&[(0, 0), (3, 0), (4, 1), (4, 2), (3, 3), (0, 3)],
+          &[(3, 3), (4, 4), (4, 5), (3, 6), (0, 6), (0, 0)]]),
+  ('C', &[&[(4, 0), (0, 0), (0, 6), (4, 6)]]),
+  ('D', &[&[(0, 0), (3, 0), (4, 1), (4, 5), (3, 6), (0, 6), (0, 0)]]),
+  ('E', &[&[(4, 0), (0, 0), (0, 6), (4, 6)], &[(0, 3), (3, 3)]]),
+  ('F', &[&[(4, 0), (0, 0), (0, 6)], &[(0, 3), (3, 3)]]),
+  ('G', &[&[(4, 0), (0, 0), (0, 6), (4, 6), (4, 3), (2, 3)]]),
+  ('H', &[&[(0, 0), (0, 6)], &[(4, 0), (4, 6)], &[(0, 3), (4, 3)]]),
+  ('I', &[&[(1, 0), (3, 0)], &[(2, 0), (2, 6)], &[(1, 6), (3, 6)]]),
+  ('J', &[&[(4, 0), (4, 6), (0, 6), (0, 4)]]),
+  ('K', &[&[(0, 0), (0, 6)], &[(4, 0), (0, 3), (4, 6)]]),
+  ('L', &[&[(0, 0), (0, 6), (4, 6)]]),
+  ('M', &[&[(0, 6), (0, 0), (2, 3), (4, 0), (4, 6)]]),
+  ('N', &[&[(0, 6), (0, 0), (4, 6), (4, 0)]]),
+  ('O', &[&[(0, 0), (4, 0), (4, 6), (0, 6), (0, 0)]]),
+  ('P', &[&[(0, 6), (0, 0), (4, 0), (4, 3), (0, 3)]]),
+  ('Q', &[&[(0, 0), (4, 0), (4, 6), (0, 6), (0, 0)], &[(2, 4), (4, 6)]]),
+  ('R', &[&[(0, 6), (0, 0), (4, 0), (4, 3), (0, 3), (4, 6)]]),
+  ('S', &[&[(4, 0), (0, 0), (0, 3), (4, 3), (4, 6), (0, 6)]]),
+  ('T', &[&[(0, 0), (4, 0)], &[(2, 0), (2, 6)]]),
+  ('U', &[&[(0, 0), (0, 6), (4, 6), (4, 0)]]),
+  ('V', &[&[(0, 0), (2, 6), (4, 0)]]),
+  ('W', &[&[(0, 0), (0, 6), (2, 3), (4, 6), (4, 0)]]),
+  ('X', &[&[(0, 0), (4, 6)], &[(4, 0), (0, 6)]]),
+  ('Y', &[&[(0, 0), (2, 3), (4, 0)], &[(2, 3), (2, 6)]]),
+  ('Z', &[&[(0, 0), (4, 0), (0, 6), (4, 6)]]),
+  ('-', &[&[(1, 3), (3, 3)]]),
+  ('+', &[&[(0, 3), (4, 3)], &[(2, 1), (2, 5)]]),
+  ('=', &[&[(0, 2), (4, 2)], &[(0, 4), (4, 4)]]),
+  ('.', &[&[(2, 5), (2, 6)]]),
+  (',', &[&[(2, 5), (1, 7)]]),
+  (':', &[&[(2, 1), (2, 2)], &[(2, 4), (2, 5)]]),
+  ('/', &[&[(4, 0), (0, 6)]]),
+  ('%', &[&[(4, 0), (0, 6)], &[(0, 0), (1, 0), (1, 1), (0, 1), (0, 0)],
+          &[(3, 5), (4, 5), (4, 6), (3, 6), (3, 5)]]),
+  ('(', &[&[(3, 0), (1, 2), (1, 4), (3, 6)]]),
+  (')', &[&[(1, 0), (3, 2), (3, 4), (1, 6)]]),
+  ('[', &[&[(3, 0), (1, 0), (1, 6), (3, 6)]]),
+  (']', &[&[(1, 0), (3, 0), (3, 6), (1, 6)]]),
+  ('<', &[&[(4, 0), (0, 3), (4, 6)]]),
+  ('>', &[&[(0, 0), (4, 3), (0, 6)]]),
+  ('_', &[&[(0, 6), (4, 6)]]),
+  ('?', &[&[(0, 1), (0, 0), (4, 0), (4, 3), (2, 3), (2, 4)],
+          &[(2, 5), (2, 6)]]),
+];
+
+/// Strokes of a character. Characters without a glyph are drawn as '?'.
+fn glyph(c: char) -> Strokes {
+  let c = c.to_uppercase().next().unwrap_or(c);
+
+  GLYPHS.iter()
+      .find(|&&(g, _)| g == c)
+      .or(GLYPHS.iter().find(|&&(g, _)| g == '?'))
+      .map(|&(_, strokes)| strokes)
+      .unwrap_or(&[])
+}
+
+/// Width of text drawn `height` pixels high.
+pub fn text_width(text: &str, height: f64) -> f64 {
+  let count = text.chars().count() as f64;
+
+  if count == 0.0 {
+    return 0.0;
+  }
+
+  let scale = height / GLYPH_HEIGHT;
+  (count * (GLYPH_WIDTH + GLYPH_SPACING) - GLYPH_SPACING) * scale
+}
+
+/// Draw text `height` pixels high, with its top left corner at `position`.
+pub fn draw_text<G: Graphics>(text: &str, position: [f64; 2], height: f64,
+                              color: [f32; 4], draw_state: &DrawState,
+                              transform: Matrix2d, gfx: &mut G) {
+  let scale = height / GLYPH_HEIGHT;
+  let line = Line::new(color, (scale / 2.0).max(0.5));
+
+  for (i, c) in text.chars().enumerate() {
+    let left = position[0] + i as f64 * (GLYPH_WIDTH + GLYPH_SPACING) * scale;
+
+    for stroke in glyph(c).iter() {
+      for segment in stroke.windows(2) {
+        let (x1, y1) = segment[0];
+        let (x2, y2) = segment[1];
+
+        line.draw([left + x1 as f64 * scale, position[1] + y1 as f64 * scale,
+                   left + x2 as f64 * scale, position[1] + y2 as f64 * scale],
+                  draw_state, transform, gfx);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_glyph() {
+    assert_eq!(glyph('a'), glyph('A'));
+    assert_eq!(glyph('~'), glyph('?'));
+    assert!(glyph(' ').is_empty());
+  }
+
+  #[test]
+  fn test_text_width() {
+    assert_eq!(text_width("", 12.0), 0.0);
+    assert_eq!(text_width("1", 12.0), 8.0);
+    assert_eq!(text_width("-100", 12.0), 44.0);
+  }
+}
@@ -0,0 +1,310 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Built-in test patterns, like the real DAC's internal abstract generator.
+//! Useful as a known-good reference, eg. to check the renderer.
+
+use protocol::Point;
+use protocol::SOURCE_GENERATOR;
+use source::PointSource;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Blanked points used to travel between strokes.
+const TRAVEL_POINTS : usize = 8;
+
+/// Points in a full-size stroke.
+const STROKE_POINTS : usize = 100;
+
+/// Lines in each direction of the grid.
+const GRID_LINES : usize = 9;
+
+/// How far the Lissajous figure turns each frame, in radians.
+const LISSAJOUS_PHASE_STEP : f64 = 0.01;
+
+const WHITE : [u16; 3] = [65535, 65535, 65535];
+
+/// Colors of the color bars, left to right.
+const BAR_COLORS : [[u16; 3]; 7] = [
+  [65535, 65535, 65535],
+  [65535, 65535, 0],
+  [0, 65535, 65535],
+  [0, 65535, 0],
+  [65535, 0, 65535],
+  [65535, 0, 0],
+  [0, 0, 65535],
+];
+
+/// Patterns the generator can draw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+  /// Concentric circles at a third, two thirds and all of full scale.
+  Circles,
+  /// A grid of lines spanning full scale.
+  Grid,
+  /// A slowly turning 3:2 Lissajous figure.
+  Lissajous,
+  /// A simplified ILDA test pattern: a full scale square with an inscribed
+  /// circle and a center cross.
+  TestPattern,
+  /// Vertical bars of primary and secondary colors.
+  ColorBars,
+}
+
+impl FromStr for Pattern {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Pattern, ()> {
+    match s {
+      "circles" => Ok(Pattern::Circles),
+      "grid" => Ok(Pattern::Grid),
+      "lissajous" => Ok(Pattern::Lissajous),
+      "test-pattern" => Ok(Pattern::TestPattern),
+      "color-bars" => Ok(Pattern::ColorBars),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Plays a pattern over and over.
+#[derive(Clone)]
+pub struct Generator {
+  pattern: Pattern,
+  /// Points of the frame being played.
+  frame: Vec<Point>,
+  /// Number of frames played.
+  frame_count: u64,
+  /// Next point to play within the frame.
+  point: usize,
+}
+
+impl Generator {
+  /// CTOR.
+  pub fn new(pattern: Pattern) -> Generator {
+    Generator {
+      pattern: pattern,
+      frame: draw(pattern, 0),
+      frame_count: 0,
+      point: 0,
+    }
+  }
+}
+
+impl PointSource for Generator {
+  fn source(&self) -> u8 {
+    SOURCE_GENERATOR
+  }
+
+  fn next_points(&mut self, max: usize) -> Vec<Point> {
+    let mut points = Vec::with_capacity(max);
+
+    while points.len() < max {
+      let end = self.frame.len().min(self.point + max - points.len());
+
+      points.extend_from_slice(&self.frame[self.point .. end]);
+      self.point = end;
+
+      if self.point >= self.frame.len() {
+        self.point = 0;
+        self.frame_count += 1;
+
+        if self.pattern == Pattern::Lissajous {
+          self.frame = draw(self.pattern, self.frame_count);
+        }
+      }
+    }
+
+    points
+  }
+}
+
+/// Draw a frame of the pattern. Only the Lissajous figure moves.
+pub fn draw(pattern: Pattern, frame_count: u64) -> Vec<Point> {
+  let mut pen = Pen::new();
+
+  match pattern {
+    Pattern::Circles => {
+      for i in 1 .. 4 {
+        let r = i as f64 / 3.0;
+        pen.curve(STROKE_POINTS * i, WHITE, |t| {
+          let a = 2.0 * PI * t;
+          (r * a.cos(), r * a.sin())
+        });
+      }
+    },
+    Pattern::Grid => {
+      for i in 0 .. GRID_LINES {
+        let p = -1.0 + 2.0 * i as f64 / (GRID_LINES - 1) as f64;
+        pen.line((-1.0, p), (1.0, p), WHITE);
+      }
+      for i in 0 .. GRID_LINES {
+        let p = -1.0 + 2.0 * i as f64 / (GRID_LINES - 1) as f64;
+        pen.line((p, -1.0), (p, 1.0), WHITE);
+      }
+    },
+    Pattern::Lissajous => {
+      let phase = frame_count as f64 * LISSAJOUS_PHASE_STEP;
+      pen.curve(STROKE_POINTS * 6, [0, 65535, 0], |t| {
+        let a = 2.0 * PI * t;
+        ((3.0 * a + phase).sin(), (2.0 * a).sin())
+      });
+    },
+    Pattern::TestPattern => {
+      pen.line((-1.0, -1.0), (1.0, -1.0), WHITE);
+      pen.line((1.0, -1.0), (1.0, 1.0), WHITE);
+      pen.line((1.0, 1.0), (-1.0, 1.0), WHITE);
+      pen.line((-1.0, 1.0), (-1.0, -1.0), WHITE);
+      pen.curve(STROKE_POINTS * 3, [0, 65535, 0], |t| {
+        let a = 2.0 * PI * t;
+        (a.cos(), a.sin())
+      });
+      pen.line((-0.25, 0.0), (0.25, 0.0), [65535, 0, 0]);
+      pen.line((0.0, -0.25), (0.0, 0.25), [0, 0, 65535]);
+    },
+    Pattern::ColorBars => {
+      let width = 2.0 / BAR_COLORS.len() as f64;
+      for (i, color) in BAR_COLORS.iter().enumerate() {
+        // Three strokes fill out each bar.
+        for j in 0 .. 3 {
+          let x = -1.0 + width * (i as f64 + (j as f64 + 1.0) / 4.0);
+          pen.line((x, 1.0), (x, -1.0), *color);
+        }
+      }
+    },
+  }
+
+  pen.close();
+  pen.points
+}
+
+/// Draws strokes in normalized coordinates (-1 to 1 on each axis), with
+/// blanked travel between them.
+struct Pen {
+  points: Vec<Point>,
+}
+
+impl Pen {
+  fn new() -> Pen {
+    Pen {
+      points: Vec::new(),
+    }
+  }
+
+  /// Draw a straight line.
+  fn line(&mut self, from: (f64, f64), to: (f64, f64), color: [u16; 3]) {
+    self.curve(STROKE_POINTS, color, |t| {
+      (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)
+    });
+  }
+
+  /// Draw a curve from `f(0)` to `f(1)` with the given number of points.
+  fn curve<F>(&mut self, num_points: usize, color: [u16; 3], f: F)
+      where F: Fn(f64) -> (f64, f64) {
+    self.travel(f(0.0));
+
+    for i in 0 .. num_points + 1 {
+      let (x, y) = f(i as f64 / num_points as f64);
+      self.points.push(make_point(x, y, color));
+    }
+  }
+
+  /// Travel back to the first point, so the frame can loop without a lit
+  /// jump.
+  fn close(&mut self) {
+    let first = match self.points.first() {
+      None => return,
+      Some(point) => (point.x as f64 / 32767.0, point.y as f64 / 32767.0),
+    };
+
+    self.travel(first);
+  }
+
+  /// Move to the start of the next stroke with the beam off.
+  fn travel(&mut self, to: (f64, f64)) {
+    let from = match self.points.last() {
+      None => return,
+      Some(point) => (point.x as f64 / 32767.0, point.y as f64 / 32767.0),
+    };
+
+    for i in 1 .. TRAVEL_POINTS + 1 {
+      let t = i as f64 / TRAVEL_POINTS as f64;
+      let x = from.0 + (to.0 - from.0) * t;
+      let y = from.1 + (to.1 - from.1) * t;
+      self.points.push(make_point(x, y, [0, 0, 0]));
+    }
+  }
+}
+
+fn make_point(x: f64, y: f64, color: [u16; 3]) -> Point {
+  let lit = color != [0, 0, 0];
+
+  Point {
+    control: 0,
+    x: (x.max(-1.0).min(1.0) * 32767.0) as i16,
+    y: (y.max(-1.0).min(1.0) * 32767.0) as i16,
+    i: if lit { 65535 } else { 0 },
+    r: color[0],
+    g: color[1],
+    b: color[2],
+    u1: 0,
+    u2: 0,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_patterns() {
+    let patterns = ["circles", "grid", "lissajous", "test-pattern",
+                    "color-bars"];
+
+    for name in patterns.iter() {
+      let pattern = Pattern::from_str(name).unwrap();
+      let frame = draw(pattern, 0);
+
+      assert!(!frame.is_empty());
+      assert!(frame.len() <= 65_535);
+    }
+
+    assert!(Pattern::from_str("spiral").is_err());
+  }
+
+  #[test]
+  fn test_patterns_loop_blanked() {
+    let patterns = [Pattern::Circles, Pattern::Grid, Pattern::Lissajous,
+                    Pattern::TestPattern, Pattern::ColorBars];
+
+    for pattern in patterns.iter() {
+      let frame = draw(*pattern, 0);
+      let first = &frame[0];
+      let last = &frame[frame.len() - 1];
+
+      // The beam returns to the start with the beam off.
+      assert_eq!((last.r, last.g, last.b, last.i), (0, 0, 0, 0));
+      assert!((last.x as i32 - first.x as i32).abs() <= 1);
+      assert!((last.y as i32 - first.y as i32).abs() <= 1);
+    }
+  }
+
+  #[test]
+  fn test_grid_spans_full_scale() {
+    let frame = draw(Pattern::Grid, 0);
+
+    assert_eq!(frame.iter().map(|p| p.x).min(), Some(-32767));
+    assert_eq!(frame.iter().map(|p| p.x).max(), Some(32767));
+    assert_eq!(frame.iter().map(|p| p.y).min(), Some(-32767));
+    assert_eq!(frame.iter().map(|p| p.y).max(), Some(32767));
+  }
+
+  #[test]
+  fn test_generator_loops() {
+    let mut generator = Generator::new(Pattern::Circles);
+    let frame = draw(Pattern::Circles, 0);
+
+    let points = generator.next_points(frame.len() + 10);
+
+    assert_eq!(&points[.. frame.len()], &frame[..]);
+    assert_eq!(&points[frame.len() ..], &frame[.. 10]);
+  }
+}
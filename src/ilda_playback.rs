@@ -0,0 +1,323 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Plays ILDA files in place of a client, like the real DAC's playback from
+//! SD card. Frames are played once each, in order, looping forever.
+
+use error::EmulatorError;
+use ilda::data::IldaEntry;
+use ilda::parser;
+use protocol::Point;
+use protocol::SOURCE_ILDA;
+use source::PointSource;
+use std::fs::File;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Size of an ILDA section header in bytes.
+const HEADER_SIZE : usize = 32;
+
+/// ILDA status code bit for blanked points.
+const STATUS_BLANKED : u8 = 1 << 6;
+
+/// Colors of indexed points in files without their own palette.
+const DEFAULT_PALETTE : [[u8; 3]; 64] = [
+  [255, 0, 0], [255, 16, 0], [255, 32, 0], [255, 48, 0],
+  [255, 64, 0], [255, 80, 0], [255, 96, 0], [255, 112, 0],
+  [255, 128, 0], [255, 144, 0], [255, 160, 0], [255, 176, 0],
+  [255, 192, 0], [255, 208, 0], [255, 224, 0], [255, 240, 0],
+  [255, 255, 0], [224, 255, 0], [192, 255, 0], [160, 255, 0],
+  [128, 255, 0], [96, 255, 0], [64, 255, 0], [32, 255, 0],
+  [0, 255, 0], [0, 255, 36], [0, 255, 73], [0, 255, 109],
+  [0, 255, 146], [0, 255, 182], [0, 255, 219], [0, 255, 255],
+  [0, 227, 255], [0, 198, 255], [0, 170, 255], [0, 142, 255],
+  [0, 113, 255], [0, 85, 255], [0, 56, 255], [0, 28, 255],
+  [0, 0, 255], [32, 0, 255], [64, 0, 255], [96, 0, 255],
+  [128, 0, 255], [160, 0, 255], [192, 0, 255], [224, 0, 255],
+  [255, 0, 255], [255, 32, 255], [255, 64, 255], [255, 96, 255],
+  [255, 128, 255], [255, 160, 255], [255, 192, 255], [255, 224, 255],
+  [255, 255, 255], [255, 224, 224], [255, 192, 192], [255, 160, 160],
+  [255, 128, 128], [255, 96, 96], [255, 64, 64], [255, 32, 32],
+];
+
+/// The frames of one or more ILDA files, and the position of playback.
+#[derive(Clone)]
+pub struct IldaPlayback {
+  frames: Vec<Vec<Point>>,
+  /// Frame being played.
+  frame: usize,
+  /// Next point to play within the frame.
+  point: usize,
+}
+
+impl IldaPlayback {
+  /// Load an ILDA file, or every ILDA file in a directory in name order.
+  pub fn open(path: &str) -> Result<IldaPlayback, EmulatorError> {
+    let mut frames = Vec::new();
+
+    for file in ilda_files(Path::new(path))? {
+      let file = file.to_string_lossy().into_owned();
+      match read_entries(&file) {
+        Err(_) => println!("Skipping unreadable ILDA file {}", file),
+        Ok(entries) => frames.extend(read_frames(&entries)),
+      }
+    }
+
+    if frames.is_empty() {
+      return Err(EmulatorError::ParseError);
+    }
+
+    Ok(IldaPlayback {
+      frames: frames,
+      frame: 0,
+      point: 0,
+    })
+  }
+
+  /// Number of frames loaded.
+  pub fn frames(&self) -> usize {
+    self.frames.len()
+  }
+}
+
+impl PointSource for IldaPlayback {
+  fn source(&self) -> u8 {
+    SOURCE_ILDA
+  }
+
+  fn next_points(&mut self, max: usize) -> Vec<Point> {
+    let mut points = Vec::with_capacity(max);
+
+    while points.len() < max {
+      let frame = &self.frames[self.frame];
+      let end = frame.len().min(self.point + max - points.len());
+
+      points.extend_from_slice(&frame[self.point .. end]);
+      self.point = end;
+
+      if self.point >= frame.len() {
+        self.point = 0;
+        self.frame = (self.frame + 1) % self.frames.len();
+      }
+    }
+
+    points
+  }
+}
+
+/// The file itself, or the ILDA files in a directory sorted by name.
+fn ilda_files(path: &Path) -> Result<Vec<PathBuf>, EmulatorError> {
+  if !path.is_dir() {
+    return Ok(vec![path.to_path_buf()]);
+  }
+
+  let mut files = Vec::new();
+
+  for entry in fs::read_dir(path)? {
+    let file = entry?.path();
+    let is_ilda = file.extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase() == "ild")
+        .unwrap_or(false);
+
+    if is_ilda {
+      files.push(file);
+    }
+  }
+
+  files.sort();
+  Ok(files)
+}
+
+/// Parse an ILDA file. The ilda parser panics on sections that run past the
+/// end of the file, so they are checked first.
+fn read_entries(path: &str) -> Result<Vec<IldaEntry>, EmulatorError> {
+  let mut bytes = Vec::new();
+  File::open(path)?.read_to_end(&mut bytes)?;
+
+  check_sections(&bytes)?;
+
+  parser::read_bytes(&bytes).map_err(|_| EmulatorError::ParseError)
+}
+
+/// Check that every section's records fit within the file.
+fn check_sections(bytes: &[u8]) -> Result<(), EmulatorError> {
+  let mut i = 0;
+
+  while i < bytes.len() {
+    if bytes.len() - i < HEADER_SIZE {
+      return Err(EmulatorError::ParseError);
+    }
+
+    let header = &bytes[i .. i + HEADER_SIZE];
+
+    let record_size = match header[7] {
+      0 => 8, // 3D indexed color
+      1 => 6, // 2D indexed color
+      2 => 3, // Color palette
+      4 => 10, // 3D true color
+      5 => 8, // 2D true color
+      _ => return Err(EmulatorError::ParseError),
+    };
+
+    let records = (header[24] as usize) << 8 | header[25] as usize;
+
+    i += HEADER_SIZE + records * record_size;
+  }
+
+  if i > bytes.len() {
+    return Err(EmulatorError::ParseError);
+  }
+
+  Ok(())
+}
+
+/// Convert parsed ILDA entries into frames of points. Palettes and empty
+/// frames are not played.
+fn read_frames(entries: &[IldaEntry]) -> Vec<Vec<Point>> {
+  let mut frames = Vec::new();
+  let mut frame = Vec::new();
+  let mut palette : Vec<[u8; 3]> = Vec::new();
+
+  for entry in entries {
+    match *entry {
+      IldaEntry::HeaderEntry(ref header) => {
+        if !frame.is_empty() {
+          frames.push(frame);
+          frame = Vec::new();
+        }
+        if header.format_code == 2 {
+          palette.clear();
+        }
+      },
+      IldaEntry::ColorPaletteEntry(ref color) => {
+        palette.push([color.r, color.g, color.b]);
+      },
+      // FIXME: ilda 0.0.2 reads the color of every format 4 point from the
+      // first point in the frame.
+      IldaEntry::TcPoint3dEntry(ref p) => {
+        frame.push(make_point(p.x, p.y, p.status_code, [p.r, p.g, p.b]));
+      },
+      IldaEntry::TcPoint2dEntry(ref p) => {
+        frame.push(make_point(p.x, p.y, p.status_code, [p.r, p.g, p.b]));
+      },
+      IldaEntry::IdxPoint3dEntry(ref p) => {
+        let color = palette_color(&palette, p.color_index);
+        frame.push(make_point(p.x, p.y, p.status_code, color));
+      },
+      IldaEntry::IdxPoint2dEntry(ref p) => {
+        let color = palette_color(&palette, p.color_index);
+        frame.push(make_point(p.x, p.y, p.status_code, color));
+      },
+    }
+  }
+
+  if !frame.is_empty() {
+    frames.push(frame);
+  }
+
+  frames
+}
+
+fn palette_color(palette: &[[u8; 3]], index: i8) -> [u8; 3] {
+  let index = index as u8 as usize;
+  if palette.is_empty() {
+    DEFAULT_PALETTE[index % DEFAULT_PALETTE.len()]
+  } else {
+    palette[index % palette.len()]
+  }
+}
+
+fn make_point(x: i16, y: i16, status_code: i8, color: [u8; 3]) -> Point {
+  let color = if status_code as u8 & STATUS_BLANKED != 0 {
+    [0, 0, 0]
+  } else {
+    color
+  };
+
+  let lit = color != [0, 0, 0];
+
+  Point {
+    control: 0,
+    x: x,
+    y: y,
+    i: if lit { 65535 } else { 0 },
+    r: color[0] as u16 * 257,
+    g: color[1] as u16 * 257,
+    b: color[2] as u16 * 257,
+    u1: 0,
+    u2: 0,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use export::IldaFormat;
+  use export::IldaWriter;
+  use std::fs::File;
+  use std::fs;
+  use std::io::Read;
+  use std::io::Write;
+  use test_util::temp_path;
+
+  #[test]
+  fn test_ilda_playback() {
+    let path = temp_path("playback.ild");
+    let path = path.to_str().unwrap();
+
+    {
+      let mut writer = IldaWriter::create(path, IldaFormat::TrueColor2d)
+          .unwrap();
+      writer.write_frame(&[make_point(1, 1, 0, [255, 0, 0]),
+                           make_point(2, 2, 0, [0, 255, 0])]).unwrap();
+      writer.write_frame(&[make_point(3, 3, 0, [0, 0, 255])]).unwrap();
+      writer.finish().unwrap();
+    }
+
+    let mut playback = IldaPlayback::open(path).unwrap();
+    assert_eq!(playback.frames(), 2);
+
+    let xs : Vec<i16> = playback.next_points(5).iter().map(|p| p.x).collect();
+    assert_eq!(xs, vec![1, 2, 3, 1, 2]);
+
+    let point = playback.next_points(1).remove(0);
+    assert_eq!(point.x, 3);
+    assert_eq!(point.b, 65535);
+    assert_eq!(point.r, 0);
+
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_truncated_file() {
+    let path = temp_path("truncated.ild");
+    let path = path.to_str().unwrap();
+
+    {
+      let mut writer = IldaWriter::create(path, IldaFormat::TrueColor2d)
+          .unwrap();
+      writer.write_frame(&[make_point(1, 1, 0, [255, 0, 0]),
+                           make_point(2, 2, 0, [0, 255, 0])]).unwrap();
+      writer.finish().unwrap();
+    }
+
+    let mut bytes = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+
+    // Cut off within the points, and then within the end of file header.
+    for &length in &[HEADER_SIZE + 12, bytes.len() - 1] {
+      File::create(path).unwrap().write_all(&bytes[.. length]).unwrap();
+      assert!(IldaPlayback::open(path).is_err());
+    }
+
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_blanked_point() {
+    let point = make_point(0, 0, STATUS_BLANKED as i8, [255, 255, 255]);
+    assert_eq!((point.r, point.g, point.b, point.i), (0, 0, 0, 0));
+  }
+}
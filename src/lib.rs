@@ -0,0 +1,57 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+// A OpenGL emulator/visualizer for the EtherDream laser projector DAC.
+// See http://ether-dream.com/protocol.html
+
+//! An emulated EtherDream laser DAC.
+//!
+//! Besides the `etherdream-emulator` program, the emulator can be embedded,
+//! eg. to test client libraries against a virtual DAC:
+//!
+//! ```no_run
+//! use etherdream_emulator::EmulatorBuilder;
+//!
+//! let emulator = EmulatorBuilder::new().start().unwrap();
+//! println!("Connect to port {}", emulator.port());
+//! // ... drive a client, then inspect emulator.status() ...
+//! emulator.shutdown();
+//! ```
+
+extern crate byteorder;
+extern crate clap;
+extern crate glium_graphics;
+extern crate graphics;
+extern crate ilda;
+extern crate net2;
+extern crate piston;
+extern crate png;
+
+pub mod broadcast;
+pub mod camera;
+pub mod capture;
+pub mod dac;
+pub mod emulator;
+pub mod error;
+pub mod export;
+pub mod font;
+pub mod generator;
+pub mod ilda_playback;
+pub mod opts;
+pub mod persistence;
+pub mod pipeline;
+pub mod protocol;
+pub mod raster;
+pub mod render;
+pub mod replay;
+pub mod scanner;
+pub mod session;
+pub mod shutdown;
+pub mod source;
+pub mod stats;
+pub mod video;
+
+#[cfg(test)]
+mod test_util;
+
+pub use emulator::EmulatorBuilder;
+pub use emulator::EmulatorHandle;
+pub use opts::RuntimeOpts;
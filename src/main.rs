@@ -2,133 +2,177 @@
 // A OpenGL emulator/visualizer for the EtherDream laser projector DAC.
 // See http://ether-dream.com/protocol.html
 
-extern crate byteorder;
-extern crate clap;
-extern crate glium_graphics;
-extern crate graphics;
-extern crate ilda;
-extern crate net2;
-extern crate piston;
-
-mod dac;
-mod error;
-mod pipeline;
-mod protocol;
-mod render;
-
-use clap::App;
-use clap::Arg;
-use dac::Dac;
-use net2::UdpBuilder;
-use pipeline::Pipeline;
-use protocol::Broadcast;
-use protocol::DacStatus;
-use render::gl_window;
-use std::net::IpAddr;
-use std::net::Ipv4Addr;
-use std::net::SocketAddr;
-use std::str::FromStr;
+extern crate ctrlc;
+extern crate etherdream_emulator;
+
+use etherdream_emulator::RuntimeOpts;
+use etherdream_emulator::broadcast::broadcast_thread;
+use etherdream_emulator::capture::Recorder;
+use etherdream_emulator::dac::Dac;
+use etherdream_emulator::export::IldaExporter;
+use etherdream_emulator::export::dac_path;
+use etherdream_emulator::generator::Generator;
+use etherdream_emulator::ilda_playback::IldaPlayback;
+use etherdream_emulator::pipeline::Pipeline;
+use etherdream_emulator::protocol::DacStatus;
+use etherdream_emulator::render::gl_window;
+use etherdream_emulator::render::offscreen;
+use etherdream_emulator::replay::replay_thread;
+use etherdream_emulator::session::SessionReader;
+use etherdream_emulator::session::convert_capture;
+use etherdream_emulator::shutdown::Shutdown;
+use etherdream_emulator::source::PointSource;
+use etherdream_emulator::source::source_thread;
 use std::sync::Arc;
-use std::thread::sleep;
+use std::sync::RwLock;
+use std::thread::JoinHandle;
 use std::thread;
-use std::time::Duration;
 
-const TCP_PORT : u16 = 7765;
-const UDP_PORT : u16 = 7654;
+fn main() {
+  let args = RuntimeOpts::read();
 
-/// Program runtime options
-#[derive(Clone,Debug)]
-pub struct RuntimeOpts {
-  /// Print debugging information to STDOUT,
-  /// eg. each message in the client/server protocol.
-  pub debug_protocol: bool,
+  if let Some((ref capture, ref session)) = args.convert_paths {
+    let frames = convert_capture(capture, session)
+        .expect("Could not convert capture file.");
+    println!("Wrote {} frames to {}", frames, session);
+    return;
+  }
 
-  /// Don't spawn a GUI.
-  pub headless: bool,
+  let shutdown = Shutdown::new();
 
-  /// Size of rendered points.
-  pub point_size: f64,
-}
+  // Stop on SIGINT and SIGTERM.
+  let shutdown2 = shutdown.clone();
+  ctrlc::set_handler(move || shutdown2.stop())
+      .expect("Could not install signal handler.");
+
+  let recorder = args.record_path.as_ref().map(|path| {
+    println!("Recording session to {}", path);
+    Arc::new(Recorder::create(path).expect("Could not create capture file."))
+  });
+
+  let ilda = args.ilda_path.as_ref().map(|path| {
+    let playback = IldaPlayback::open(path)
+        .expect("Could not read ILDA files.");
+    println!("Playing {} ILDA frames from {}", playback.frames(), path);
+    playback
+  });
+
+  let mut pipelines = Vec::new();
+  let mut threads = Vec::new();
+
+  if let Some(ref path) = args.replay_path {
+    println!("Replaying {}", path);
+
+    let num_dacs = SessionReader::open(path)
+        .expect("Could not read session file.")
+        .dacs();
+    let mut dacs = Vec::new();
+
+    for _i in 0 .. num_dacs {
+      let status = Arc::new(RwLock::new(DacStatus::empty()));
+      let pipeline = Arc::new(Pipeline::new(status.clone(),
+                                            args.buffer_capacity));
+      pipeline.set_debug(args.debug_protocol);
+      let pipeline2 = pipeline.clone();
+
+      let shutdown2 = shutdown.clone();
+      threads.push(thread::spawn(move || pipeline2.process(&shutdown2)));
+
+      dacs.push((status, pipeline.clone()));
+      pipelines.push(pipeline);
+    }
+
+    let replay_args = args.clone();
+    let shutdown2 = shutdown.clone();
+    threads.push(thread::spawn(move || {
+      replay_thread(&replay_args, dacs, &shutdown2)
+    }));
+  } else {
+    let configs = args.dac_configs()
+        .expect("Too many DACs to fit on consecutive ports.");
+
+    for config in configs {
+      println!("Virtual DAC {}:{}", config.bind_address, config.tcp_port);
+
+      let status = Arc::new(RwLock::new(DacStatus::empty()));
+      let pipeline = Arc::new(Pipeline::new(status.clone(),
+                                            args.buffer_capacity));
+      pipeline.set_debug(args.debug_protocol);
+      let pipeline2 = pipeline.clone();
 
-impl RuntimeOpts {
-  fn read() -> RuntimeOpts {
-    let matches = App::new("etherdream-emulator")
-        .arg(Arg::with_name("debug")
-             .long("debug")
-             .short("d")
-             .help("Turns debugging output on")
-             .takes_value(false)
-             .required(false))
-        .arg(Arg::with_name("headless")
-             .long("headless")
-             .help("Turns off the GUI")
-             .takes_value(false)
-             .required(false))
-        .arg(Arg::with_name("point")
-             .long("point")
-             .help("Changes size of drawn points")
-             .takes_value(true)
-             .required(false))
-        .get_matches();
-
-    RuntimeOpts {
-      debug_protocol: matches.is_present("debug"),
-      headless: matches.is_present("headless"),
-      point_size: matches.value_of("point")
-        .map_or(None, |s| f64::from_str(s).ok())
-        .unwrap_or(1.0)
+      let mut dac = Dac::new(&args, &config, status.clone(), pipeline.clone())
+          .unwrap();
+
+      if let Some(ref recorder) = recorder {
+        dac.set_recorder(recorder.clone());
+      }
+
+      if let Some(ref ilda) = ilda {
+        threads.push(spawn_source(ilda.clone(), &args, &status, &pipeline,
+                                  &shutdown));
+      }
+
+      if let Some(pattern) = args.generator {
+        threads.push(spawn_source(Generator::new(pattern), &args, &status,
+                                  &pipeline, &shutdown));
+      }
+
+      let broadcast_args = args.clone();
+
+      let shutdown2 = shutdown.clone();
+      threads.push(thread::spawn(move || {
+        broadcast_thread(&broadcast_args, &config, status, &shutdown2)
+      }));
+
+      let shutdown2 = shutdown.clone();
+      threads.push(thread::spawn(move || pipeline2.process(&shutdown2)));
+
+      let shutdown2 = shutdown.clone();
+      threads.push(thread::spawn(move || dac.run(&shutdown2)));
+
+      pipelines.push(pipeline);
     }
   }
-}
 
-fn main() {
-  let args = RuntimeOpts::read();
+  if let Some(ref path) = args.export_path {
+    for (i, pipeline) in pipelines.iter().enumerate() {
+      let path = dac_path(path, i, pipelines.len());
+      println!("Exporting ILDA to {}", path);
 
-  let pipeline = Arc::new(Pipeline::new());
-  let pipeline2 = pipeline.clone();
+      let exporter = IldaExporter::create(&path, args.export_format,
+                                          args.export_segmentation)
+          .expect("Could not create ILDA file.");
+      pipeline.set_exporter(exporter).unwrap();
+    }
+  }
 
-  let dac = Dac::new(&args, pipeline.clone());
+  if args.headless {
+    // Returns on a signal.
+    offscreen(pipelines, &args, &shutdown);
+  } else {
+    // Returns when the window is closed or on a signal.
+    gl_window(pipelines, &args, &shutdown);
+  }
 
-  thread::spawn(|| broadcast_thread());
-  thread::spawn(move || gl_window(pipeline2, &args));
-  thread::spawn(move || pipeline.process());
+  println!("Shutting down.");
+  shutdown.stop();
 
-  dac.run();
+  for thread in threads {
+    let _r = thread.join();
+  }
 }
 
-/// Send a UDP broadcast announcing the EtherDream to the network.
-fn broadcast_thread() {
-  let udp = UdpBuilder::new_v4().unwrap();
-  udp.reuse_address(true).unwrap();
-
-  let socket = udp.bind("0.0.0.0:0").unwrap();
-  socket.set_broadcast(true).unwrap();
-
-  let multicast_ip = Ipv4Addr::new(255, 255, 255, 255);
-  let multicast_socket = SocketAddr::new(IpAddr::V4(multicast_ip), UDP_PORT);
-
-  let broadcast = Broadcast {
-      mac_address: vec![1, 2, 3, 4, 5, 255],
-      hw_revision: 9000,
-      sw_revision: 8000,
-      buffer_capacity: 1000,
-      max_point_rate: 1234567890,
-      status: DacStatus {
-        protocol: 1,
-        light_engine_state: 2,
-        playback_state: 3,
-        source: 4,
-        light_engine_flags: 5,
-        playback_flags: 6,
-        source_flags: 7,
-        buffer_fullness: 8,
-        point_rate: 9,
-        point_count: 10,
-      },
-  };
-
-  loop {
-    sleep(Duration::from_secs(1));
-    socket.send_to(&broadcast.serialize(), multicast_socket).unwrap();
-  }
+/// Feed a DAC from a source other than the network, on its own thread.
+fn spawn_source<S>(source: S, args: &RuntimeOpts,
+                   status: &Arc<RwLock<DacStatus>>, pipeline: &Arc<Pipeline>,
+                   shutdown: &Shutdown) -> JoinHandle<()>
+    where S: PointSource + Send + 'static {
+  let point_rate = args.source_point_rate;
+  let status = status.clone();
+  let pipeline = pipeline.clone();
+  let shutdown = shutdown.clone();
+
+  thread::spawn(move || {
+    source_thread(source, point_rate, status, pipeline, &shutdown)
+  })
 }
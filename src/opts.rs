@@ -0,0 +1,689 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+use clap::App;
+use clap::Arg;
+use dac::DacConfig;
+use error::EmulatorError;
+use export::IldaFormat;
+use export::Segmentation;
+use generator::Pattern;
+use render::Channel;
+use render::RenderMode;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::Duration;
+use std::u16;
+
+const TCP_PORT : u16 = 7765;
+const UDP_PORT : u16 = 7654;
+
+/// Milliseconds between UDP broadcasts.
+const BROADCAST_INTERVAL_MS : u64 = 1000;
+
+/// Default milliseconds drawn points take to fade out.
+const PERSISTENCE_MS : u64 = 0;
+
+/// Default size of headless snapshots and of video.
+const IMAGE_SIZE : [u32; 2] = [600, 600];
+
+/// Default frames per second of recorded video.
+const VIDEO_FPS : u32 = 30;
+
+/// Default furthest modeled scanners move between points at their rated
+/// speed, in ILDA units. About the steps of the ILDA test pattern.
+const SCANNER_MAX_STEP : u16 = 2_048;
+
+/// Default damping ratio of modeled scanners. Slightly underdamped, as
+/// scanners are usually tuned.
+const SCANNER_DAMPING : f64 = 0.7;
+
+/// Number of points a real EtherDream can buffer.
+const BUFFER_CAPACITY : u16 = 1800;
+
+/// Default MAC address of the first virtual DAC.
+const MAC_ADDRESS : [u8; 6] = [1, 2, 3, 4, 5, 0];
+
+/// Default hardware and software revisions reported in broadcasts.
+const HW_REVISION : u16 = 2;
+const SW_REVISION : u16 = 2;
+
+/// Default maximum point rate reported in broadcasts.
+const MAX_POINT_RATE : u32 = 100_000;
+
+/// Default point rate of sources other than the network.
+const SOURCE_POINT_RATE : u32 = 30_000;
+
+/// Program runtime options
+#[derive(Clone,Debug)]
+pub struct RuntimeOpts {
+  /// Print debugging information to STDOUT,
+  /// eg. each message in the client/server protocol.
+  pub debug_protocol: bool,
+
+  /// Don't spawn a GUI.
+  pub headless: bool,
+
+  /// How points are drawn.
+  pub render_mode: RenderMode,
+
+  /// Size of rendered points.
+  pub point_size: f64,
+
+  /// Width of rendered lines.
+  pub line_width: f64,
+
+  /// Multiplier applied to rendered colors.
+  pub brightness: f64,
+
+  /// Draw blanked points in a dim color, and mark points with control bits.
+  pub show_blanking: bool,
+
+  /// Channel of the points drawn.
+  pub channel: Channel,
+
+  /// Draw a grid labeled in ILDA units.
+  pub show_grid: bool,
+
+  /// Show each DAC's connection, playback and traffic statistics.
+  pub show_hud: bool,
+
+  /// How long drawn points take to fade out.
+  pub persistence: Duration,
+
+  /// PNG file to save snapshots of the drawn points to, if any.
+  pub snapshot_path: Option<String>,
+
+  /// Time between snapshots when headless, or None for just one at
+  /// shutdown.
+  pub snapshot_interval: Option<Duration>,
+
+  /// File, or numbered files, to record video of the drawn points to.
+  pub video_path: Option<String>,
+
+  /// Frames per second of recorded video.
+  pub video_fps: u32,
+
+  /// Width and height of headless snapshots and of video frames.
+  pub image_size: [u32; 2],
+
+  /// Speed rating of the modeled scanners in kpps, or None to draw points
+  /// exactly where they are addressed.
+  pub scanner_kpps: Option<u32>,
+
+  /// Furthest the modeled scanners move between points at their rated
+  /// speed, in ILDA units.
+  pub scanner_max_step: u16,
+
+  /// Damping ratio of the modeled scanners.
+  pub scanner_damping: f64,
+
+  /// Number of points the DAC can buffer.
+  pub buffer_capacity: u16,
+
+  /// Number of virtual DACs to run.
+  pub num_dacs: usize,
+
+  /// Local interface addresses to listen and broadcast on, one per DAC.
+  pub bind_addresses: Vec<Ipv4Addr>,
+
+  /// TCP port to accept client connections on.
+  pub tcp_port: u16,
+
+  /// Destination address of UDP broadcasts.
+  pub broadcast_address: Ipv4Addr,
+
+  /// Destination port of UDP broadcasts.
+  pub broadcast_port: u16,
+
+  /// Time between UDP broadcasts.
+  pub broadcast_interval: Duration,
+
+  /// MAC address of the first DAC. Each subsequent DAC increments the last
+  /// byte.
+  pub mac_address: [u8; 6],
+
+  /// Hardware revision reported in broadcasts.
+  pub hw_revision: u16,
+
+  /// Software revision reported in broadcasts.
+  pub sw_revision: u16,
+
+  /// Maximum point rate reported in broadcasts.
+  pub max_point_rate: u32,
+
+  /// File to record the session to, if any.
+  pub record_path: Option<String>,
+
+  /// Capture file to convert into a session file, and the session file, if
+  /// converting rather than running.
+  pub convert_paths: Option<(String, String)>,
+
+  /// Session file to play back instead of accepting clients, if any.
+  pub replay_path: Option<String>,
+
+  /// Replay speed multiplier.
+  pub replay_speed: f64,
+
+  /// Start the replay over when it ends.
+  pub replay_loop: bool,
+
+  /// ILDA file to export played points to, if any.
+  pub export_path: Option<String>,
+
+  /// ILDA format of exported files.
+  pub export_format: IldaFormat,
+
+  /// How exported points are cut into ILDA frames.
+  pub export_segmentation: Segmentation,
+
+  /// ILDA file, or directory of files, to play instead of network streams.
+  pub ilda_path: Option<String>,
+
+  /// Test pattern to play instead of network streams, if any.
+  pub generator: Option<Pattern>,
+
+  /// Point rate of sources other than the network.
+  pub source_point_rate: u32,
+}
+
+impl RuntimeOpts {
+  /// Read options from the command line.
+  pub fn read() -> RuntimeOpts {
+    let matches = App::new("etherdream-emulator")
+        .arg(Arg::with_name("debug")
+             .long("debug")
+             .short("d")
+             .help("Turns debugging output on")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("headless")
+             .long("headless")
+             .help("Turns off the GUI")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("render")
+             .long("render")
+             .help("Draws a dot per point to check point density (the \
+                    default), or lines between points like the scanning beam")
+             .takes_value(true)
+             .possible_values(&["lines", "dots"])
+             .required(false))
+        .arg(Arg::with_name("point")
+             .long("point")
+             .help("Changes size of drawn points")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("line-width")
+             .long("line-width")
+             .help("Changes width of drawn lines")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("brightness")
+             .long("brightness")
+             .help("Multiplies drawn colors, eg. 2 to brighten dim scans")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("show-blanking")
+             .long("show-blanking")
+             .help("Draws blanked points in a dim color, and marks points \
+                    with control bits set. Press B in the window to toggle.")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("channel")
+             .long("channel")
+             .help("Draws the color, or the intensity or a user channel in \
+                    grayscale. Press C in the window to cycle through them.")
+             .takes_value(true)
+             .possible_values(&["color", "i", "u1", "u2"])
+             .required(false))
+        .arg(Arg::with_name("grid")
+             .long("grid")
+             .help("Draws a grid labeled in ILDA units. Press G in the \
+                    window to toggle.")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("hud")
+             .long("hud")
+             .help("Shows connection, playback and traffic statistics for \
+                    each DAC. Press H in the window to toggle.")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("persistence")
+             .long("persistence")
+             .help("Milliseconds drawn points take to fade out, like the \
+                    afterglow the eye sees")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("snapshot")
+             .long("snapshot")
+             .help("Saves a PNG of the drawn points. Headless, one is saved \
+                    at shutdown; in the window, press S.")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("snapshot-interval")
+             .long("snapshot-interval")
+             .help("Saves a numbered headless snapshot every this many \
+                    milliseconds")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("video")
+             .long("video")
+             .help("Records video of the drawn points, as a y4m stream if \
+                    the file ends in .y4m, or else as numbered PNGs")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("video-fps")
+             .long("video-fps")
+             .help("Frames per second of recorded video")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("image-size")
+             .long("image-size")
+             .help("Size of headless snapshots and of video, eg. 600x600")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("scanner")
+             .long("scanner")
+             .help("Draws points where galvo scanners of this kpps rating \
+                    would actually point, eg. 30")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("scanner-max-step")
+             .long("scanner-max-step")
+             .help("Furthest the scanners move between points at their \
+                    rated speed, in ILDA units")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("scanner-damping")
+             .long("scanner-damping")
+             .help("Damping ratio of the scanners; below 1 they overshoot")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("buffer")
+             .long("buffer")
+             .help("Number of points the DAC can buffer")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("dacs")
+             .long("dacs")
+             .help("Number of virtual DACs to run")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("bind")
+             .long("bind")
+             .help("Local IPv4 address to listen and broadcast on. \
+                    Repeat to give each DAC its own address; DACs without \
+                    an address share the last one on consecutive ports.")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .required(false))
+        .arg(Arg::with_name("port")
+             .long("port")
+             .help("TCP port to listen on")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("broadcast")
+             .long("broadcast")
+             .help("IPv4 address to send UDP broadcasts to")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("broadcast-port")
+             .long("broadcast-port")
+             .help("UDP port to send broadcasts to")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("broadcast-interval")
+             .long("broadcast-interval")
+             .help("Milliseconds between UDP broadcasts")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("mac")
+             .long("mac")
+             .help("MAC address of the first DAC, eg. 01:02:03:04:05:00")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("hw-revision")
+             .long("hw-revision")
+             .help("Hardware revision reported in broadcasts")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("sw-revision")
+             .long("sw-revision")
+             .help("Software revision reported in broadcasts")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("max-point-rate")
+             .long("max-point-rate")
+             .help("Maximum point rate reported in broadcasts")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("record")
+             .long("record")
+             .help("Records every connection and command to a capture file")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("convert-capture")
+             .long("convert-capture")
+             .help("Converts a capture file into a session file for replay, \
+                    then exits")
+             .takes_value(true)
+             .number_of_values(2)
+             .value_names(&["CAPTURE", "SESSION"])
+             .required(false))
+        .arg(Arg::with_name("replay")
+             .long("replay")
+             .help("Plays back a session file instead of accepting clients. \
+                    Press space in the window to pause.")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("replay-speed")
+             .long("replay-speed")
+             .help("Replay speed multiplier, eg. 0.5 for half speed")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("replay-loop")
+             .long("replay-loop")
+             .help("Starts the replay over when it ends")
+             .takes_value(false)
+             .required(false))
+        .arg(Arg::with_name("export")
+             .long("export")
+             .help("Exports played points to an ILDA file. With several \
+                    DACs, each gets a numbered file.")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("export-format")
+             .long("export-format")
+             .help("ILDA format to export, 4 (3D) or 5 (2D)")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("export-window")
+             .long("export-window")
+             .help("Cuts exported frames every this many milliseconds, \
+                    instead of where the scan repeats")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("ilda")
+             .long("ilda")
+             .help("Plays an ILDA file, or a directory of them, like the \
+                    real DAC's SD card. Clients can connect, but not play.")
+             .takes_value(true)
+             .required(false))
+        .arg(Arg::with_name("generator")
+             .long("generator")
+             .help("Plays a test pattern, like the real DAC's abstract \
+                    generator. Clients can connect, but not play.")
+             .takes_value(true)
+             .possible_values(&["circles", "grid", "lissajous",
+                                "test-pattern", "color-bars"])
+             .conflicts_with("ilda")
+             .required(false))
+        .arg(Arg::with_name("point-rate")
+             .long("point-rate")
+             .help("Point rate of ILDA playback and the generator")
+             .takes_value(true)
+             .required(false))
+        .get_matches();
+
+    let bind_addresses : Vec<Ipv4Addr> = matches.values_of("bind")
+        .map_or(Vec::new(), |values| {
+          values.filter_map(|s| Ipv4Addr::from_str(s).ok()).collect()
+        });
+
+    let bind_addresses = if bind_addresses.is_empty() {
+      vec![Ipv4Addr::new(0, 0, 0, 0)]
+    } else {
+      bind_addresses
+    };
+
+    RuntimeOpts {
+      debug_protocol: matches.is_present("debug"),
+      headless: matches.is_present("headless"),
+      render_mode: matches.value_of("render")
+        .map_or(None, |s| RenderMode::from_str(s).ok())
+        .unwrap_or(RenderMode::Dots),
+      point_size: matches.value_of("point")
+        .map_or(None, |s| f64::from_str(s).ok())
+        .unwrap_or(1.0),
+      line_width: matches.value_of("line-width")
+        .map_or(None, |s| f64::from_str(s).ok())
+        .unwrap_or(1.0),
+      brightness: matches.value_of("brightness")
+        .map_or(None, |s| f64::from_str(s).ok())
+        .and_then(|b| if b >= 0.0 { Some(b) } else { None })
+        .unwrap_or(1.0),
+      show_blanking: matches.is_present("show-blanking"),
+      channel: matches.value_of("channel")
+        .map_or(None, |s| Channel::from_str(s).ok())
+        .unwrap_or(Channel::Color),
+      show_grid: matches.is_present("grid"),
+      show_hud: matches.is_present("hud"),
+      persistence: matches.value_of("persistence")
+        .map_or(None, |s| u64::from_str(s).ok())
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_millis(PERSISTENCE_MS)),
+      snapshot_path: matches.value_of("snapshot").map(|s| s.to_string()),
+      snapshot_interval: matches.value_of("snapshot-interval")
+        .map_or(None, |s| u64::from_str(s).ok())
+        .and_then(|ms| if ms > 0 { Some(ms) } else { None })
+        .map(Duration::from_millis),
+      video_path: matches.value_of("video").map(|s| s.to_string()),
+      video_fps: matches.value_of("video-fps")
+        .map_or(None, |s| u32::from_str(s).ok())
+        .and_then(|fps| if fps > 0 { Some(fps) } else { None })
+        .unwrap_or(VIDEO_FPS),
+      image_size: matches.value_of("image-size")
+        .map_or(None, parse_size)
+        .unwrap_or(IMAGE_SIZE),
+      scanner_kpps: matches.value_of("scanner")
+        .map_or(None, |s| u32::from_str(s).ok())
+        .and_then(|kpps| if kpps > 0 { Some(kpps) } else { None }),
+      scanner_max_step: matches.value_of("scanner-max-step")
+        .map_or(None, |s| u16::from_str(s).ok())
+        .unwrap_or(SCANNER_MAX_STEP),
+      scanner_damping: matches.value_of("scanner-damping")
+        .map_or(None, |s| f64::from_str(s).ok())
+        .unwrap_or(SCANNER_DAMPING),
+      buffer_capacity: matches.value_of("buffer")
+        .map_or(None, |s| u16::from_str(s).ok())
+        .unwrap_or(BUFFER_CAPACITY),
+      num_dacs: matches.value_of("dacs")
+        .map_or(None, |s| usize::from_str(s).ok())
+        .unwrap_or(bind_addresses.len())
+        .max(1),
+      bind_addresses: bind_addresses,
+      tcp_port: matches.value_of("port")
+        .map_or(None, |s| u16::from_str(s).ok())
+        .unwrap_or(TCP_PORT),
+      broadcast_address: matches.value_of("broadcast")
+        .map_or(None, |s| Ipv4Addr::from_str(s).ok())
+        .unwrap_or(Ipv4Addr::new(255, 255, 255, 255)),
+      broadcast_port: matches.value_of("broadcast-port")
+        .map_or(None, |s| u16::from_str(s).ok())
+        .unwrap_or(UDP_PORT),
+      broadcast_interval: matches.value_of("broadcast-interval")
+        .map_or(None, |s| u64::from_str(s).ok())
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_millis(BROADCAST_INTERVAL_MS)),
+      mac_address: matches.value_of("mac")
+        .map_or(None, parse_mac_address)
+        .unwrap_or(MAC_ADDRESS),
+      hw_revision: matches.value_of("hw-revision")
+        .map_or(None, |s| u16::from_str(s).ok())
+        .unwrap_or(HW_REVISION),
+      sw_revision: matches.value_of("sw-revision")
+        .map_or(None, |s| u16::from_str(s).ok())
+        .unwrap_or(SW_REVISION),
+      max_point_rate: matches.value_of("max-point-rate")
+        .map_or(None, |s| u32::from_str(s).ok())
+        .unwrap_or(MAX_POINT_RATE),
+      record_path: matches.value_of("record").map(|s| s.to_string()),
+      convert_paths: matches.values_of("convert-capture").map(|mut paths| {
+        let capture = paths.next().unwrap_or("").to_string();
+        let session = paths.next().unwrap_or("").to_string();
+        (capture, session)
+      }),
+      replay_path: matches.value_of("replay").map(|s| s.to_string()),
+      replay_speed: matches.value_of("replay-speed")
+        .map_or(None, |s| f64::from_str(s).ok())
+        .and_then(|speed| if speed > 0.0 { Some(speed) } else { None })
+        .unwrap_or(1.0),
+      replay_loop: matches.is_present("replay-loop"),
+      export_path: matches.value_of("export").map(|s| s.to_string()),
+      export_format: matches.value_of("export-format")
+        .map_or(None, |s| u8::from_str(s).ok())
+        .and_then(IldaFormat::from_code)
+        .unwrap_or(IldaFormat::TrueColor2d),
+      export_segmentation: matches.value_of("export-window")
+        .map_or(None, |s| u64::from_str(s).ok())
+        .map(|ms| Segmentation::Window(Duration::from_millis(ms)))
+        .unwrap_or(Segmentation::Loop),
+      ilda_path: matches.value_of("ilda").map(|s| s.to_string()),
+      generator: matches.value_of("generator")
+        .map_or(None, |s| Pattern::from_str(s).ok()),
+      source_point_rate: matches.value_of("point-rate")
+        .map_or(None, |s| u32::from_str(s).ok())
+        .unwrap_or(SOURCE_POINT_RATE),
+    }
+  }
+
+  /// Network identity of each virtual DAC. DACs take the bind addresses in
+  /// order, all on the same TCP port. Any DACs left over share the last
+  /// address on consecutive ports, which must not run past 65535.
+  pub fn dac_configs(&self) -> Result<Vec<DacConfig>, EmulatorError> {
+    let last = self.bind_addresses.len() - 1;
+
+    (0 .. self.num_dacs).map(|i| {
+      let mut mac_address = self.mac_address;
+      mac_address[5] = mac_address[5].wrapping_add(i as u8);
+
+      let (bind_address, tcp_port) = if i <= last {
+        (self.bind_addresses[i], self.tcp_port)
+      } else {
+        let offset = i - last;
+        let tcp_port = match self.tcp_port.checked_add(offset as u16) {
+          Some(port) if offset <= u16::MAX as usize => port,
+          _ => return Err(EmulatorError::InvalidConfig),
+        };
+        (self.bind_addresses[last], tcp_port)
+      };
+
+      Ok(DacConfig {
+        index: i,
+        bind_address: bind_address,
+        tcp_port: tcp_port,
+        mac_address: mac_address,
+      })
+    }).collect()
+  }
+}
+
+impl Default for RuntimeOpts {
+  fn default() -> RuntimeOpts {
+    RuntimeOpts {
+      debug_protocol: false,
+      headless: false,
+      render_mode: RenderMode::Dots,
+      point_size: 1.0,
+      line_width: 1.0,
+      brightness: 1.0,
+      show_blanking: false,
+      channel: Channel::Color,
+      show_grid: false,
+      show_hud: false,
+      persistence: Duration::from_millis(PERSISTENCE_MS),
+      snapshot_path: None,
+      snapshot_interval: None,
+      video_path: None,
+      video_fps: VIDEO_FPS,
+      image_size: IMAGE_SIZE,
+      scanner_kpps: None,
+      scanner_max_step: SCANNER_MAX_STEP,
+      scanner_damping: SCANNER_DAMPING,
+      buffer_capacity: BUFFER_CAPACITY,
+      num_dacs: 1,
+      bind_addresses: vec![Ipv4Addr::new(0, 0, 0, 0)],
+      tcp_port: TCP_PORT,
+      broadcast_address: Ipv4Addr::new(255, 255, 255, 255),
+      broadcast_port: UDP_PORT,
+      broadcast_interval: Duration::from_millis(BROADCAST_INTERVAL_MS),
+      mac_address: MAC_ADDRESS,
+      hw_revision: HW_REVISION,
+      sw_revision: SW_REVISION,
+      max_point_rate: MAX_POINT_RATE,
+      record_path: None,
+      convert_paths: None,
+      replay_path: None,
+      replay_speed: 1.0,
+      replay_loop: false,
+      export_path: None,
+      export_format: IldaFormat::TrueColor2d,
+      export_segmentation: Segmentation::Loop,
+      ilda_path: None,
+      generator: None,
+      source_point_rate: SOURCE_POINT_RATE,
+    }
+  }
+}
+
+/// Parse a MAC address written as six colon-separated hex bytes.
+fn parse_mac_address(s: &str) -> Option<[u8; 6]> {
+  let parts : Vec<&str> = s.split(':').collect();
+
+  if parts.len() != 6 {
+    return None;
+  }
+
+  let mut mac_address = [0u8; 6];
+
+  for (i, part) in parts.iter().enumerate() {
+    match u8::from_str_radix(part, 16) {
+      Err(_) => return None,
+      Ok(b) => mac_address[i] = b,
+    }
+  }
+
+  Some(mac_address)
+}
+
+/// Parse an image size written as WIDTHxHEIGHT.
+fn parse_size(s: &str) -> Option<[u32; 2]> {
+  let parts : Vec<&str> = s.split('x').collect();
+
+  if parts.len() != 2 {
+    return None;
+  }
+
+  match (u32::from_str(parts[0]), u32::from_str(parts[1])) {
+    (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+      Some([width, height])
+    },
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use error::EmulatorError;
+
+  #[test]
+  fn test_dac_configs() {
+    let opts = RuntimeOpts {
+      num_dacs: 3,
+      tcp_port: 65534,
+      ..RuntimeOpts::default()
+    };
+
+    // One port is left for the second DAC, but not the third.
+    match opts.dac_configs() {
+      Err(EmulatorError::InvalidConfig) => {},
+      _ => panic!("Ports past 65535 should be rejected."),
+    }
+
+    let opts = RuntimeOpts { num_dacs: 2, ..opts };
+    let configs = opts.dac_configs().unwrap();
+    assert_eq!(configs[1].tcp_port, 65535);
+  }
+}
@@ -0,0 +1,165 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Afterglow of drawn points. The eye sees a laser scan by persistence of
+//! vision, so points stay on screen and fade out rather than vanishing when
+//! the next batch is drawn.
+
+use protocol::Point;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Natural log of 256. Points fading at this rate reach 1/256 of their
+/// brightness, invisible at 8 bits per channel, at the end of the fade.
+const FADE_RATE : f64 = 5.545;
+
+/// A batch of points drawn together.
+pub struct Trace {
+  /// When the points were taken from the pipeline to be drawn. Fades are
+  /// measured from this, not from when the points were played.
+  pub time: Instant,
+  /// Where the beam was before the first point, so lines join up.
+  pub previous: Option<Point>,
+  pub points: Vec<Point>,
+}
+
+/// Recently played points of a DAC, fading out over time.
+pub struct Persistence {
+  /// How long points take to fade out. Zero shows only the latest batch.
+  fade: Duration,
+  /// Traces still visible, oldest first.
+  traces: VecDeque<Trace>,
+  /// Time of the latest batch, which fades are measured from.
+  now: Option<Instant>,
+  /// The last point pushed, even if later batches were empty.
+  last: Option<Point>,
+}
+
+impl Persistence {
+  /// CTOR.
+  pub fn new(fade: Duration) -> Persistence {
+    Persistence {
+      fade: fade,
+      traces: VecDeque::new(),
+      now: None,
+      last: None,
+    }
+  }
+
+  /// Add a batch of points taken to be drawn at `now`, dropping points that
+  /// have faded.
+  pub fn push(&mut self, now: Instant, points: Vec<Point>) {
+    let previous = self.last.clone();
+
+    if let Some(point) = points.last() {
+      self.last = Some(point.clone());
+    }
+
+    self.traces.push_back(Trace {
+      time: now,
+      previous: previous,
+      points: points,
+    });
+
+    self.now = Some(now);
+
+    // NB: The latest trace is always kept, even with no persistence.
+    while self.traces.len() > 1 {
+      let expired = self.traces.front()
+          .map(|t| now.duration_since(t.time) >= self.fade)
+          .unwrap_or(false);
+
+      if !expired {
+        break;
+      }
+
+      self.traces.pop_front();
+    }
+  }
+
+  /// Visible traces, oldest first.
+  pub fn traces(&self) -> &VecDeque<Trace> {
+    &self.traces
+  }
+
+  /// Remaining brightness of a trace, from 1.0 down to 0.0.
+  pub fn fade(&self, trace: &Trace) -> f32 {
+    let age = match self.now {
+      Some(now) => now.duration_since(trace.time),
+      None => return 1.0,
+    };
+
+    if age == Duration::from_millis(0) {
+      return 1.0;
+    }
+
+    if age >= self.fade {
+      return 0.0;
+    }
+
+    let age = age.as_secs() as f64 + age.subsec_nanos() as f64 / 1e9;
+    let fade = self.fade.as_secs() as f64
+        + self.fade.subsec_nanos() as f64 / 1e9;
+
+    (-FADE_RATE * age / fade).exp() as f32
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn points(x: i16) -> Vec<Point> {
+    vec![Point {
+      control: 0, x: x, y: 0, i: 0, r: 0, g: 0, b: 0, u1: 0, u2: 0,
+    }]
+  }
+
+  #[test]
+  fn test_persistence() {
+    let start = Instant::now();
+    let mut persistence = Persistence::new(Duration::from_millis(100));
+
+    persistence.push(start, points(1));
+    persistence.push(start + Duration::from_millis(50), points(2));
+
+    {
+      let traces = persistence.traces();
+      assert_eq!(traces.len(), 2);
+      assert_eq!(traces[1].previous, Some(points(1).remove(0)));
+
+      let fade = persistence.fade(&traces[0]);
+      assert!(fade > 0.05 && fade < 0.07); // 1/16, halfway through.
+      assert_eq!(persistence.fade(&traces[1]), 1.0);
+    }
+
+    persistence.push(start + Duration::from_millis(120), points(3));
+    assert_eq!(persistence.traces().len(), 2);
+    assert_eq!(persistence.traces()[0].points[0].x, 2);
+  }
+
+  #[test]
+  fn test_empty_batch() {
+    let start = Instant::now();
+    let mut persistence = Persistence::new(Duration::from_millis(0));
+
+    persistence.push(start, points(1));
+    persistence.push(start, Vec::new());
+    persistence.push(start, points(2));
+
+    // Lines still join up across a batch with no points.
+    assert_eq!(persistence.traces()[0].previous, Some(points(1).remove(0)));
+  }
+
+  #[test]
+  fn test_no_persistence() {
+    let start = Instant::now();
+    let mut persistence = Persistence::new(Duration::from_millis(0));
+
+    persistence.push(start, points(1));
+    persistence.push(start, points(2));
+
+    assert_eq!(persistence.traces().len(), 1);
+    assert_eq!(persistence.fade(&persistence.traces()[0]), 1.0);
+  }
+}
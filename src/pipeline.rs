@@ -4,98 +4,376 @@ use byteorder::LittleEndian;
 use byteorder::ReadBytesExt;
 use dac::DacFrame;
 use error::EmulatorError;
+use export::IldaExporter;
+use protocol::DacStatus;
+use protocol::PLAYBACK_FLAG_UNDERFLOW;
+use protocol::PLAYBACK_IDLE;
+use protocol::PLAYBACK_PLAYING;
+use protocol::POINT_CONTROL_RATE_CHANGE;
 use protocol::Point;
+use protocol::ResponseState;
+use shutdown::Shutdown;
+use stats::Stats;
 use std::collections::VecDeque;
 use std::io::Cursor;
+use std::sync::Arc;
 use std::sync::Mutex;
-use std::thread;
+use std::sync::RwLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
+use std::time::Instant;
 
-/// A separate thread to consume raw points off the wire and translate them into
-/// graphical points ready to render. This takes load off the DAC thread as well
-/// as the OpenGL/drawing thread.
+/// Number of point rate changes that may be queued at once.
+const RATE_QUEUE_LIMIT : usize = 8;
+
+/// How long the playback thread sleeps between clock ticks.
+const TICK_INTERVAL_MS : u64 = 1;
+
+/// The playback system of the virtual DAC. Points sent over the network are
+/// buffered here, and a separate thread plays them back at the configured
+/// point rate. Played points are handed off to the OpenGL/drawing thread.
 pub struct Pipeline {
-  input: Mutex<VecDeque<DacFrame>>,
+  /// Points waiting to be played.
+  buffer: Mutex<VecDeque<Point>>,
+  /// Points that have been played, waiting to be drawn.
   output: Mutex<VecDeque<Point>>,
-  frame_limit: usize,
+  /// Point rates queued by the client, applied during playback.
+  rate_changes: Mutex<VecDeque<u32>>,
+  /// When playback last ran out of points.
+  last_underflow: Mutex<Option<Instant>>,
+  /// Whether playback is frozen, eg. while reviewing a replay.
+  paused: AtomicBool,
+  /// Whether to print playback events to STDOUT.
+  debug: AtomicBool,
+  /// Where to export played points as ILDA, if anywhere.
+  exporter: Mutex<Option<IldaExporter>>,
+  /// Running totals of what was received and played.
+  stats: Mutex<Stats>,
+  /// Runtime state of the virtual dac, shared with the network thread.
+  status: Arc<RwLock<DacStatus>>,
+  /// Maximum number of points waiting to be played.
+  buffer_capacity: usize,
+  /// Maximum number of played points waiting to be drawn.
   point_limit: usize,
 }
 
 impl Pipeline {
   /// CTOR.
-  pub fn new() -> Pipeline {
+  pub fn new(status: Arc<RwLock<DacStatus>>, buffer_capacity: u16)
+             -> Pipeline {
     Pipeline {
-      input: Mutex::new(VecDeque::new()),
+      buffer: Mutex::new(VecDeque::new()),
       output: Mutex::new(VecDeque::new()),
-      frame_limit: 10,
+      rate_changes: Mutex::new(VecDeque::new()),
+      last_underflow: Mutex::new(None),
+      paused: AtomicBool::new(false),
+      debug: AtomicBool::new(false),
+      exporter: Mutex::new(None),
+      stats: Mutex::new(Stats::new()),
+      status: status,
+      buffer_capacity: buffer_capacity as usize,
       point_limit: 5_000,
     }
   }
 
-  /// Enqueue frames from the network thread.
+  /// Enqueue frames from the network thread. Frames that do not entirely fit
+  /// in the buffer are rejected.
   pub fn enqueue(&self, frame: DacFrame) -> Result<(), EmulatorError> {
-    let mut lock = self.input.lock()?;
-    if (*lock).len() > self.frame_limit {
+    self.enqueue_points(parse_points(frame))
+  }
+
+  /// Enqueue points from a source other than the network. Points that do
+  /// not entirely fit in the buffer are rejected.
+  pub fn enqueue_points(&self, points: Vec<Point>)
+                        -> Result<(), EmulatorError> {
+    let mut lock = self.buffer.lock()?;
+    if (*lock).len() + points.len() > self.buffer_capacity {
       return Err(EmulatorError::PipelineFull);
     }
-    (*lock).push_back(frame);
+    self.stats.lock()?.points_received += points.len() as u64;
+    (*lock).extend(points);
     Ok(())
   }
 
-  /// Dequeue points from the graphics thread.
-  pub fn dequeue(&self, num_points: usize)
-                 -> Result<Vec<Point>, EmulatorError> {
-    let mut buf = Vec::new();
+  /// Number of points that can be buffered at once.
+  pub fn buffer_capacity(&self) -> usize {
+    self.buffer_capacity
+  }
+
+  /// Dequeue all played points from the graphics thread.
+  pub fn dequeue(&self) -> Result<Vec<Point>, EmulatorError> {
     let mut lock = self.output.lock()?;
+    Ok((*lock).drain(..).collect())
+  }
 
-    while buf.len() < num_points {
-      match (*lock).pop_front() {
-        None => return Ok(buf), // Return fewer frames than asked for.
-        Some(frame) => buf.push(frame),
-      }
+  /// Queue a point rate change from the network thread.
+  pub fn queue_rate_change(&self, point_rate: u32)
+                           -> Result<(), EmulatorError> {
+    let mut lock = self.rate_changes.lock()?;
+    if (*lock).len() >= RATE_QUEUE_LIMIT {
+      return Err(EmulatorError::PipelineFull);
     }
-    Ok(buf)
+    (*lock).push_back(point_rate);
+    Ok(())
+  }
+
+  /// Drop all queued points and rate changes, eg. when the DAC is re-prepared.
+  pub fn clear(&self) -> Result<(), EmulatorError> {
+    self.buffer.lock()?.clear();
+    self.output.lock()?.clear();
+    self.rate_changes.lock()?.clear();
+    Ok(())
   }
 
-  /// Get the queue size.
+  /// Get the number of points waiting to be played.
   pub fn queue_size(&self) -> Result<usize, EmulatorError> {
-    let mut lock = self.input.lock()?;
+    let lock = self.buffer.lock()?;
     Ok((*lock).len())
   }
 
-  /// Run by a separate thread from network and graphics.
-  pub fn process(&self) -> ! {
-    loop {
-      let frame = {
-        let mut lock = self.input.lock().unwrap(); // Fatal error.
-        lock.pop_front()
-      };
+  /// Runtime state of the virtual dac.
+  pub fn status(&self) -> Result<DacStatus, EmulatorError> {
+    Ok(self.status.read()?.clone())
+  }
+
+  /// Current point rate, or zero when not playing.
+  pub fn point_rate(&self) -> Result<u32, EmulatorError> {
+    Ok(self.status.read()?.point_rate)
+  }
+
+  /// When playback last ran out of points, if ever.
+  pub fn last_underflow(&self) -> Result<Option<Instant>, EmulatorError> {
+    Ok(*self.last_underflow.lock()?)
+  }
+
+  /// Freeze or resume playback. Buffered points are kept while paused.
+  pub fn set_paused(&self, paused: bool) {
+    self.paused.store(paused, Ordering::SeqCst);
+  }
+
+  /// Whether playback is frozen.
+  pub fn is_paused(&self) -> bool {
+    self.paused.load(Ordering::SeqCst)
+  }
+
+  /// Print playback events, such as underflows, to STDOUT.
+  pub fn set_debug(&self, debug: bool) {
+    self.debug.store(debug, Ordering::SeqCst);
+  }
+
+  /// Running totals of what was received and played.
+  pub fn stats(&self) -> Result<Stats, EmulatorError> {
+    Ok(self.stats.lock()?.clone())
+  }
+
+  /// Note a client connecting or disconnecting.
+  pub fn set_connected(&self, connected: bool) -> Result<(), EmulatorError> {
+    self.stats.lock()?.connected = connected;
+    Ok(())
+  }
+
+  /// Count a command from the client, and the response it got.
+  pub fn count_response(&self, response: ResponseState)
+                        -> Result<(), EmulatorError> {
+    self.stats.lock()?.count_response(response);
+    Ok(())
+  }
+
+  /// Export points to an ILDA file as they are played.
+  pub fn set_exporter(&self, exporter: IldaExporter)
+                      -> Result<(), EmulatorError> {
+    *self.exporter.lock()? = Some(exporter);
+    Ok(())
+  }
+
+  /// Run by a separate thread from network and graphics. Plays buffered
+  /// points at the point rate while the DAC is Playing, until shutdown.
+  pub fn process(&self, shutdown: &Shutdown) {
+    let mut clock : Option<PlaybackClock> = None;
+
+    while !shutdown.wait(Duration::from_millis(TICK_INTERVAL_MS)) {
+      self.tick(&mut clock, Instant::now()).unwrap(); // Fatal error.
+    }
+  }
+
+  /// Play all points that have come due since the last tick.
+  fn tick(&self, clock: &mut Option<PlaybackClock>, now: Instant)
+          -> Result<(), EmulatorError> {
+    let exporting = self.exporter.lock()?.is_some();
+
+    // Points played and the rate each played at, for the exporter. It
+    // writes to disk, so it gets them once the locks below are released.
+    let mut exported : Vec<(Point, u32)> = Vec::new();
+
+    {
+      // NB: Lock status before the queues, same as the network thread.
+      let mut status = self.status.write()?;
 
-      let frame = match frame {
-        Some(f) => f,
-        None => {
-          thread::sleep(Duration::from_millis(100)); // No work to do.
-          continue;
-        },
+      if status.playback_state != PLAYBACK_PLAYING || status.point_rate == 0
+          || self.is_paused() {
+        *clock = None;
+        return Ok(());
+      }
+
+      let reset = match *clock {
+        None => true,
+        Some(ref c) => c.point_rate != status.point_rate,
       };
 
-      let points = parse_points(frame);
+      if reset {
+        *clock = Some(PlaybackClock::new(status.point_rate, now));
+      }
+
+      let clock = clock.as_mut().unwrap(); // Set above.
+
+      let due = clock.due(now);
+      clock.advance(due);
+
+      let mut buffer = self.buffer.lock()?;
+      let mut output = self.output.lock()?;
+      let mut played = 0;
+      let mut starved = false;
+
+      // Points played at the current rate, and the time of those before.
+      let mut run = 0;
+      let mut time_played = Duration::from_secs(0);
+
+      while played < due {
+        let point = match (*buffer).pop_front() {
+          None => {
+            starved = true;
+            break;
+          },
+          Some(point) => point,
+        };
 
-      let mut lock = self.output.lock().unwrap(); // Fatal error.
+        played += 1;
+        run += 1;
 
-      for point in points {
-        if (*lock).len() > self.point_limit {
-          break; // TODO: Don't generate extra wasted points if over limit.
+        let rate_change = point.control & POINT_CONTROL_RATE_CHANGE != 0;
+
+        if exporting {
+          exported.push((point.clone(), status.point_rate));
+        }
+
+        if (*output).len() >= self.point_limit {
+          (*output).pop_front(); // Nobody is drawing; drop the oldest points.
+        }
+        (*output).push_back(point);
+
+        if rate_change {
+          if let Some(point_rate) = self.rate_changes.lock()?.pop_front() {
+            time_played += points_duration(run, status.point_rate);
+            run = 0;
+
+            // The rest of the points due this tick are still owed, and the
+            // new rate takes over from here.
+            status.point_rate = point_rate;
+            *clock = PlaybackClock::new(point_rate, now);
+          }
         }
-        (*lock).push_back(point);
       }
+
+      status.point_count = status.point_count.wrapping_add(played as u32);
+      status.buffer_fullness = (*buffer).len() as u16;
+
+      time_played += points_duration(run, status.point_rate);
+
+      let mut stats = self.stats.lock()?;
+      stats.points_played += played;
+      stats.time_played += time_played;
+
+      if starved {
+        stats.underflows += 1;
+
+        if self.debug.load(Ordering::SeqCst) {
+          println!("Playback underflow after {} points.", status.point_count);
+        }
+
+        // The stream ends, and stays ended until the client prepares again.
+        status.playback_state = PLAYBACK_IDLE;
+        status.playback_flags |= PLAYBACK_FLAG_UNDERFLOW;
+        status.point_rate = 0;
+        status.point_count = 0;
+
+        self.rate_changes.lock()?.clear();
+        *self.last_underflow.lock()? = Some(now);
+      }
+    }
+
+    if exported.is_empty() {
+      return Ok(());
+    }
+
+    let mut exporter = self.exporter.lock()?;
+
+    let export_failed = match *exporter {
+      None => false,
+      Some(ref mut e) => {
+        exported.into_iter().any(|(point, rate)| e.push(point, rate).is_err())
+      },
+    };
+
+    if export_failed {
+      println!("ILDA export failed; no longer exporting.");
+      *exporter = None;
+    }
+
+    Ok(())
+  }
+}
+
+/// How long a number of points take to play at a point rate.
+#[inline]
+fn points_duration(points: u64, point_rate: u32) -> Duration {
+  if point_rate == 0 {
+    return Duration::from_secs(0); // Nothing plays at a rate of zero.
+  }
+
+  let nanos = points * 1_000_000_000 / point_rate as u64;
+  Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+/// Keeps playback in step with wall-clock time at a fixed point rate.
+struct PlaybackClock {
+  /// Points per second.
+  point_rate: u32,
+  /// When the point rate took effect.
+  start: Instant,
+  /// Points accounted for since `start`.
+  elapsed_points: u64,
+}
+
+impl PlaybackClock {
+  fn new(point_rate: u32, start: Instant) -> PlaybackClock {
+    PlaybackClock {
+      point_rate: point_rate,
+      start: start,
+      elapsed_points: 0,
     }
   }
+
+  /// Number of points that have come due by `now` and are not yet
+  /// accounted for.
+  fn due(&self, now: Instant) -> u64 {
+    let elapsed = now.duration_since(self.start);
+    let rate = self.point_rate as u64;
+    let total = elapsed.as_secs() * rate
+        + elapsed.subsec_nanos() as u64 * rate / 1_000_000_000;
+    total.saturating_sub(self.elapsed_points)
+  }
+
+  /// Account for points. Time passes even if the buffer is starved, so
+  /// there is never a burst of catch-up playback.
+  fn advance(&mut self, points: u64) {
+    self.elapsed_points += points;
+  }
 }
 
 /// Parse raw point bytes into structured Points.
 #[inline]
-fn parse_points(dac_frame: DacFrame) -> Vec<Point> {
+pub fn parse_points(dac_frame: DacFrame) -> Vec<Point> {
   let mut reader = Cursor::new(dac_frame.point_data);
   let mut points : Vec<Point> = Vec::new();
 
@@ -115,3 +393,145 @@ fn parse_points(dac_frame: DacFrame) -> Vec<Point> {
   }
   points
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_clock_due() {
+    let start = Instant::now();
+    let mut clock = PlaybackClock::new(30_000, start);
+
+    assert_eq!(clock.due(start), 0);
+    assert_eq!(clock.due(start + Duration::from_millis(10)), 300);
+    assert_eq!(clock.due(start + Duration::from_secs(2)), 60_000);
+
+    clock.advance(300);
+    assert_eq!(clock.due(start + Duration::from_millis(10)), 0);
+    assert_eq!(clock.due(start + Duration::from_millis(15)), 150);
+  }
+
+  #[test]
+  fn test_tick_plays_at_point_rate() {
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Pipeline::new(status.clone(), 1_800);
+
+    pipeline.enqueue(make_frame(1_000)).unwrap();
+
+    {
+      let mut status = status.write().unwrap();
+      status.playback_state = PLAYBACK_PLAYING;
+      status.point_rate = 10_000;
+    }
+
+    let start = Instant::now();
+    let mut clock = None;
+
+    pipeline.tick(&mut clock, start).unwrap();
+    pipeline.tick(&mut clock, start + Duration::from_millis(25)).unwrap();
+
+    assert_eq!(pipeline.dequeue().unwrap().len(), 250);
+    assert_eq!(pipeline.queue_size().unwrap(), 750);
+
+    let status = status.read().unwrap().clone();
+    assert_eq!(status.point_count, 250);
+    assert_eq!(status.buffer_fullness, 750);
+
+    let stats = pipeline.stats().unwrap();
+    assert_eq!(stats.points_received, 1_000);
+    assert_eq!(stats.points_played, 250);
+  }
+
+  #[test]
+  fn test_tick_rate_change() {
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Pipeline::new(status.clone(), 1_800);
+
+    // The 50th point switches from 10kpps to 20kpps.
+    let mut points = parse_points(make_frame(1_000));
+    points[49].control = POINT_CONTROL_RATE_CHANGE;
+    pipeline.enqueue_points(points).unwrap();
+    pipeline.queue_rate_change(20_000).unwrap();
+
+    {
+      let mut status = status.write().unwrap();
+      status.playback_state = PLAYBACK_PLAYING;
+      status.point_rate = 10_000;
+    }
+
+    let start = Instant::now();
+    let mut clock = None;
+
+    pipeline.tick(&mut clock, start).unwrap();
+
+    // All 100 points due are played, though the rate changed midway.
+    pipeline.tick(&mut clock, start + Duration::from_millis(10)).unwrap();
+    assert_eq!(status.read().unwrap().point_count, 100);
+    assert_eq!(status.read().unwrap().point_rate, 20_000);
+
+    pipeline.tick(&mut clock, start + Duration::from_millis(20)).unwrap();
+    assert_eq!(status.read().unwrap().point_count, 300);
+
+    // 50 points at 10kpps, then 250 at 20kpps.
+    assert_eq!(pipeline.stats().unwrap().time_played,
+               Duration::new(0, 17_500_000));
+  }
+
+  #[test]
+  fn test_enqueue_capacity() {
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Pipeline::new(status, 1_800);
+
+    pipeline.enqueue(make_frame(1_000)).unwrap();
+
+    match pipeline.enqueue(make_frame(801)) {
+      Err(EmulatorError::PipelineFull) => {},
+      _ => panic!("Frame should not fit in the buffer."),
+    }
+
+    pipeline.enqueue(make_frame(800)).unwrap();
+    pipeline.enqueue(make_frame(0)).unwrap();
+
+    assert_eq!(pipeline.queue_size().unwrap(), 1_800);
+  }
+
+  #[test]
+  fn test_tick_underflow() {
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Pipeline::new(status.clone(), 1_800);
+
+    pipeline.enqueue(make_frame(100)).unwrap();
+
+    {
+      let mut status = status.write().unwrap();
+      status.playback_state = PLAYBACK_PLAYING;
+      status.point_rate = 10_000;
+    }
+
+    let start = Instant::now();
+    let mut clock = None;
+
+    pipeline.tick(&mut clock, start).unwrap();
+    pipeline.tick(&mut clock, start + Duration::from_millis(10)).unwrap();
+
+    // Exactly drained, but not yet starved.
+    assert_eq!(status.read().unwrap().playback_state, PLAYBACK_PLAYING);
+    assert!(pipeline.last_underflow().unwrap().is_none());
+
+    pipeline.tick(&mut clock, start + Duration::from_millis(11)).unwrap();
+
+    let status = status.read().unwrap().clone();
+    assert_eq!(status.playback_state, PLAYBACK_IDLE);
+    assert_eq!(status.playback_flags, PLAYBACK_FLAG_UNDERFLOW);
+    assert!(pipeline.last_underflow().unwrap().is_some());
+    assert_eq!(pipeline.stats().unwrap().underflows, 1);
+  }
+
+  fn make_frame(num_points: u16) -> DacFrame {
+    DacFrame {
+      num_points: num_points,
+      point_data: vec![0u8; 18 * num_points as usize],
+    }
+  }
+}
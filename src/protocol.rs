@@ -7,13 +7,39 @@ use byteorder::LittleEndian;
 use byteorder::WriteBytesExt;
 use std::fmt;
 
-pub const COMMAND_BEGIN : u8   = 0x62;
-pub const COMMAND_DATA : u8    = 0x64;
-pub const COMMAND_PREPARE : u8 = 0x70;
-pub const COMMAND_VERSION : u8 = 0x76;
+pub const COMMAND_BEGIN : u8             = 0x62;
+pub const COMMAND_CLEAR_ESTOP : u8       = 0x63;
+pub const COMMAND_DATA : u8              = 0x64;
+pub const COMMAND_ESTOP : u8             = 0x00;
+pub const COMMAND_ESTOP_ALT : u8         = 0xff;
+pub const COMMAND_PING : u8              = 0x3f;
+pub const COMMAND_PREPARE : u8           = 0x70;
+pub const COMMAND_QUEUE_RATE_CHANGE : u8 = 0x71;
+pub const COMMAND_STOP : u8              = 0x73;
+pub const COMMAND_VERSION : u8           = 0x76;
+
+pub const LIGHT_ENGINE_READY : u8 = 0;
+pub const LIGHT_ENGINE_ESTOP : u8 = 3;
+
+/// Bit in `light_engine_flags`: E-Stop due to E-Stop packet or invalid command.
+pub const LIGHT_ENGINE_FLAG_ESTOP_PACKET : u16 = 1 << 0;
+
+pub const PLAYBACK_IDLE : u8     = 0;
+pub const PLAYBACK_PREPARED : u8 = 1;
+pub const PLAYBACK_PLAYING : u8  = 2;
+
+/// Bit in `playback_flags`: the last stream ended with underflow.
+pub const PLAYBACK_FLAG_UNDERFLOW : u16 = 1 << 1;
+
+/// Bit in `playback_flags`: the last stream ended due to an E-Stop.
+pub const PLAYBACK_FLAG_ESTOP : u16 = 1 << 2;
+
+pub const SOURCE_NETWORK : u8   = 0;
+pub const SOURCE_ILDA : u8      = 1;
+pub const SOURCE_GENERATOR : u8 = 2;
 
 /** The DAC periodically sends state information. */
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct DacStatus {
   pub protocol: u8,
 
@@ -151,14 +177,35 @@ impl DacStatus {
 
 pub enum Command {
   /// Begin playback command.
+  ///
+  /// This causes the DAC to begin producing output. If the playback
+  /// system was Prepared and there was data in the buffer, then the DAC
+  /// will reply with ACK; otherwise, it replies with NAK - Invalid.
   Begin {
     /// Unused.
     low_water_mark: u16,
     /// Point Rate.
     point_rate : u32,
   },
+  /// Single byte: 'c' (0x63)
+  ///
+  /// If the light engine was in E-Stop state due to an emergency stop
+  /// command (rather than a local E-Stop or a fault condition), and the
+  /// E-Stop input to the projector is not currently active, this
+  /// command causes the light engine to return to the Ready state. It
+  /// is ACKed if the light engine is now Ready, otherwise NAK - Stop
+  /// Condition.
   ClearEStop,
+
+  /// Single byte: 0x00 or 0xff
+  ///
+  /// This command causes the light engine to enter the E-Stop state,
+  /// regardless of its previous state. It is always ACKed.
   EmergencyStop,
+
+  /// Single byte: '?' (0x3f)
+  ///
+  /// The DAC will reply to this with an ACK packet.
   Ping,
 
   /// Single byte: 'p' (0x70)
@@ -170,7 +217,23 @@ pub enum Command {
   /// replies with ACK; otherwise, it replies with NAK - Invalid
   Prepare,
 
-  QueueRateChange,
+  /// Queue rate change: 'q' (0x71), followed by a u32 point rate.
+  ///
+  /// This adds a new point rate to the point rate buffer. Point rate
+  /// changes are read out of the buffer when a point with an
+  /// appropriate flag is played; see the Write Data command. If the
+  /// DAC's playback state is not Prepared or Playing, it replies with
+  /// NAK - Invalid. If the point rate buffer is full, it replies with
+  /// NAK - Full. Otherwise, it replies with ACK.
+  QueueRateChange {
+    point_rate: u32,
+  },
+
+  /// Single byte: 's' (0x73)
+  ///
+  /// This command causes the DAC to immediately stop playing and
+  /// return to the Idle state. It is ACKed if the DAC was Prepared or
+  /// Playing, and otherwise NAK - Invalid.
   Stop,
 
   /// This provides data for the DAC to add to its buffer. The data
@@ -183,6 +246,8 @@ pub enum Command {
   /// will still be ACKed (as long as the DAC is Prepared or Playing.)
   Data {
     num_points: u16,
+    /// Raw point bytes following the command header.
+    point_data: Vec<u8>,
   },
 
   /// Get the DAC version.
@@ -199,7 +264,7 @@ impl Command {
       Command::EmergencyStop=> 0x00,    // also recognizes 0xff
       Command::Ping => 0x3f,            // '?'
       Command::Prepare => 0x70,         // 'p'
-      Command::QueueRateChange => 0x74, // 'q'
+      Command::QueueRateChange { .. } => 0x71, // 'q'
       Command::Stop => 0x73,            // 's'
       Command::Version => 0x76,         // 'v'
     }
@@ -213,7 +278,7 @@ impl Command {
       Command::EmergencyStop=> "EmergencyStop",
       Command::Ping => "Ping",
       Command::Prepare => "Prepare",
-      Command::QueueRateChange => "QueueRateChange",
+      Command::QueueRateChange { .. } => "QueueRateChange",
       Command::Stop => "Stop",
       Command::Version => "Version",
     }
@@ -236,8 +301,8 @@ impl fmt::Display for Command {
           "Ping".to_string(),
       Command::Prepare =>
           "Prepare".to_string(),
-      Command::QueueRateChange =>
-          "QueueRateChange".to_string(),
+      Command::QueueRateChange { point_rate } =>
+          format!("QueueRateChange: point_rate {}", point_rate),
       Command::Stop =>
           "Stop".to_string(),
       Command::Version =>
@@ -265,6 +330,13 @@ struct DataCommand {
   dac_points: Vec<Point>,
 }
 
+/// Bit in `Point.control`: if set, and there are any values in the point
+/// rate change buffer, then the next value is read out of the buffer and
+/// set as the current playback rate. If the rate buffer is empty, the bit
+/// is ignored.
+pub const POINT_CONTROL_RATE_CHANGE : u16 = 1 << 15;
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Point {
   pub control: u16,
   pub x: i16,
@@ -278,6 +350,7 @@ pub struct Point {
 }
 
 // TODO BETTER NAME
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ResponseState {
   Ack,
   BufferFull,
@@ -338,8 +411,7 @@ impl DacResponse {
 
 // 16 bytes + dac status -> 36 bytes
 pub struct Broadcast {
-  pub mac_address : Vec<u8>, // TODO: fixed size
-  //uint8_t mac_address[6];
+  pub mac_address : [u8; 6],
   pub hw_revision : u16,
   pub sw_revision : u16,
   pub buffer_capacity : u16,
@@ -350,7 +422,7 @@ pub struct Broadcast {
 impl Broadcast {
   pub fn new() -> Broadcast {
     Broadcast {
-      mac_address: Vec::new(),
+      mac_address: [0u8; 6],
       hw_revision: 0u16,
       sw_revision: 0u16,
       buffer_capacity: 0u16,
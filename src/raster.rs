@@ -0,0 +1,269 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! A software rasterizer, so the emulator can draw without a GPU or a
+//! display, eg. to take PNG snapshots in CI.
+
+use error::EmulatorError;
+use graphics::Graphics;
+use graphics::ImageSize;
+use graphics::draw_state::DrawState;
+use png::BitDepth;
+use png::ColorType;
+use png::Encoder;
+use png::HasParameters;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::io;
+
+/// Samples taken along each axis of a pixel, to smooth edges.
+const SUPERSAMPLING : usize = 2;
+
+/// Textures aren't supported; nothing the emulator draws uses them.
+pub struct NoTexture;
+
+impl ImageSize for NoTexture {
+  fn get_size(&self) -> (u32, u32) {
+    (0, 0)
+  }
+}
+
+/// An RGB image drawn in memory, alpha blending everything drawn on it.
+/// Scissor rectangles are [x, y, width, height] in pixels from the bottom
+/// left, as in OpenGL.
+pub struct Canvas {
+  width: u32,
+  height: u32,
+  /// Color of each sample, row by row.
+  samples: Vec<[f32; 3]>,
+}
+
+impl Canvas {
+  /// CTOR. The canvas starts out black.
+  pub fn new(width: u32, height: u32) -> Canvas {
+    let samples = width as usize * height as usize
+        * SUPERSAMPLING * SUPERSAMPLING;
+
+    Canvas {
+      width: width,
+      height: height,
+      samples: vec![[0.0, 0.0, 0.0]; samples],
+    }
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// The image as 8 bit RGB, row by row.
+  pub fn to_rgb(&self) -> Vec<u8> {
+    let width = self.width as usize;
+    let height = self.height as usize;
+    let samples_per_pixel = (SUPERSAMPLING * SUPERSAMPLING) as f32;
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in 0 .. height {
+      for x in 0 .. width {
+        let mut sum = [0.0f32; 3];
+
+        for sy in 0 .. SUPERSAMPLING {
+          for sx in 0 .. SUPERSAMPLING {
+            let sample = self.sample_index(x * SUPERSAMPLING + sx,
+                                           y * SUPERSAMPLING + sy);
+            for c in 0 .. 3 {
+              sum[c] += self.samples[sample][c];
+            }
+          }
+        }
+
+        for c in 0 .. 3 {
+          let level = sum[c] / samples_per_pixel;
+          rgb.push((level.max(0.0).min(1.0) * 255.0).round() as u8);
+        }
+      }
+    }
+
+    rgb
+  }
+
+  /// Save the image as a PNG file.
+  pub fn write_png(&self, path: &str) -> Result<(), EmulatorError> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    {
+      let mut encoder = Encoder::new(&mut file, self.width, self.height);
+      encoder.set(ColorType::RGB).set(BitDepth::Eight);
+
+      let mut writer = encoder.write_header().map_err(io::Error::from)?;
+      writer.write_image_data(&self.to_rgb()).map_err(io::Error::from)?;
+
+      // NB: Dropping the writer writes the end of the image.
+      drop(writer);
+    }
+
+    file.flush()?;
+    Ok(())
+  }
+
+  #[inline]
+  fn sample_index(&self, x: usize, y: usize) -> usize {
+    y * self.width as usize * SUPERSAMPLING + x
+  }
+
+  /// Fill a triangle given in normalized device coordinates, within the
+  /// scissor rectangle if any.
+  fn triangle(&mut self, vertices: &[f32], color: &[f32; 4],
+              scissor: Option<[u32; 4]>) {
+    let samples_wide = (self.width as usize * SUPERSAMPLING) as f32;
+    let samples_high = (self.height as usize * SUPERSAMPLING) as f32;
+
+    // NB: Device coordinates run from -1 to 1, with y pointing up.
+    let x = |i: usize| (vertices[i * 2] + 1.0) / 2.0 * samples_wide;
+    let y = |i: usize| (1.0 - vertices[i * 2 + 1]) / 2.0 * samples_high;
+    let (x0, y0, x1, y1, x2, y2) = (x(0), y(0), x(1), y(1), x(2), y(2));
+
+    let area = (x1 - x0) * (y2 - y0) - (y1 - y0) * (x2 - x0);
+
+    if area == 0.0 {
+      return;
+    }
+
+    let (clip_left, clip_top, clip_right, clip_bottom) = match scissor {
+      None => (0.0, 0.0, samples_wide, samples_high),
+      Some(rect) => {
+        let ss = SUPERSAMPLING as f32;
+        let bottom = self.height.saturating_sub(rect[1]) as f32;
+        let top = (bottom - rect[3] as f32).max(0.0);
+        (rect[0] as f32 * ss, top * ss, (rect[0] + rect[2]) as f32 * ss,
+         bottom * ss)
+      },
+    };
+
+    let min_x = x0.min(x1).min(x2).floor().max(clip_left) as usize;
+    let min_y = y0.min(y1).min(y2).floor().max(clip_top) as usize;
+    let max_x = x0.max(x1).max(x2).ceil()
+        .min(clip_right).min(samples_wide) as usize;
+    let max_y = y0.max(y1).max(y2).ceil()
+        .min(clip_bottom).min(samples_high) as usize;
+
+    let alpha = color[3];
+
+    for sy in min_y .. max_y {
+      for sx in min_x .. max_x {
+        let (px, py) = (sx as f32 + 0.5, sy as f32 + 0.5);
+
+        // Which side of each edge the sample is on, relative to the winding.
+        let w0 = ((x1 - px) * (y2 - py) - (y1 - py) * (x2 - px)) * area;
+        let w1 = ((x2 - px) * (y0 - py) - (y2 - py) * (x0 - px)) * area;
+        let w2 = ((x0 - px) * (y1 - py) - (y0 - py) * (x1 - px)) * area;
+
+        if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+          continue;
+        }
+
+        let index = self.sample_index(sx, sy);
+        let sample = &mut self.samples[index];
+
+        for c in 0 .. 3 {
+          sample[c] = color[c] * alpha + sample[c] * (1.0 - alpha);
+        }
+      }
+    }
+  }
+}
+
+impl Graphics for Canvas {
+  type Texture = NoTexture;
+
+  fn clear_color(&mut self, color: [f32; 4]) {
+    for sample in self.samples.iter_mut() {
+      *sample = [color[0], color[1], color[2]];
+    }
+  }
+
+  fn clear_stencil(&mut self, _value: u8) {}
+
+  fn tri_list<F>(&mut self, draw_state: &DrawState, color: &[f32; 4],
+                 mut f: F) where F: FnMut(&mut FnMut(&[f32])) {
+    f(&mut |vertices: &[f32]| {
+      for triangle in vertices.chunks(6) {
+        if triangle.len() == 6 {
+          self.triangle(triangle, color, draw_state.scissor);
+        }
+      }
+    });
+  }
+
+  fn tri_list_uv<F>(&mut self, draw_state: &DrawState, color: &[f32; 4],
+                    _texture: &NoTexture, mut f: F)
+      where F: FnMut(&mut FnMut(&[f32], &[f32])) {
+    self.tri_list(draw_state, color, |g| f(&mut |vertices, _uv| g(vertices)));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use graphics::Context;
+  use graphics::Rectangle;
+  use std::fs;
+  use std::io::Read;
+  use test_util::temp_path;
+
+  #[test]
+  fn test_rectangle() {
+    let mut canvas = Canvas::new(4, 4);
+    let ctx = Context::new_abs(4.0, 4.0);
+
+    canvas.clear_color([0.0, 0.0, 1.0, 1.0]);
+    Rectangle::new([1.0, 0.0, 0.0, 1.0])
+      .draw([0.0, 0.0, 2.0, 2.0], &ctx.draw_state, ctx.transform, &mut canvas);
+    Rectangle::new([0.0, 1.0, 0.0, 0.5])
+      .draw([2.0, 2.0, 2.0, 2.0], &ctx.draw_state, ctx.transform, &mut canvas);
+
+    let rgb = canvas.to_rgb();
+    let pixel = |x: usize, y: usize| {
+      let i = (y * 4 + x) * 3;
+      [rgb[i], rgb[i + 1], rgb[i + 2]]
+    };
+
+    assert_eq!(pixel(0, 0), [255, 0, 0]);
+    assert_eq!(pixel(1, 1), [255, 0, 0]);
+    assert_eq!(pixel(2, 0), [0, 0, 255]);
+    assert_eq!(pixel(3, 3), [0, 128, 128]);
+  }
+
+  #[test]
+  fn test_scissor() {
+    let mut canvas = Canvas::new(4, 4);
+    let ctx = Context::new_abs(4.0, 4.0);
+
+    // Only the top right quarter, counting from the bottom left.
+    let draw_state = ctx.draw_state.scissor([2, 2, 2, 2]);
+    Rectangle::new([1.0, 1.0, 1.0, 1.0])
+      .draw([0.0, 0.0, 4.0, 4.0], &draw_state, ctx.transform, &mut canvas);
+
+    let rgb = canvas.to_rgb();
+    assert_eq!(rgb[(0 * 4 + 3) * 3], 255);
+    assert_eq!(rgb[(0 * 4 + 1) * 3], 0);
+    assert_eq!(rgb[(3 * 4 + 3) * 3], 0);
+  }
+
+  #[test]
+  fn test_write_png() {
+    let path = temp_path("canvas.png");
+    let path = path.to_str().unwrap();
+
+    Canvas::new(3, 2).write_png(path).unwrap();
+
+    let mut bytes = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+    assert_eq!(&bytes[1 .. 4], b"PNG");
+
+    fs::remove_file(path).unwrap();
+  }
+}
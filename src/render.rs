@@ -1,16 +1,39 @@
 // Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
 
 use RuntimeOpts;
+use camera::Camera;
+use font::draw_text;
+use font::text_width;
 use glium_graphics::Glium2d;
 use glium_graphics::GliumWindow;
 use glium_graphics::OpenGL;
 use graphics::*;
 use ilda::limit;
+use persistence::Persistence;
+use persistence::Trace;
 use pipeline::Pipeline;
 use piston::input::*;
 use piston::window::WindowSettings;
-use std::process;
+use protocol::DacStatus;
+use protocol::LIGHT_ENGINE_ESTOP;
+use protocol::PLAYBACK_IDLE;
+use protocol::PLAYBACK_PLAYING;
+use protocol::PLAYBACK_PREPARED;
+use protocol::POINT_CONTROL_RATE_CHANGE;
+use protocol::Point;
+use protocol::SOURCE_GENERATOR;
+use protocol::SOURCE_ILDA;
+use raster::Canvas;
+use scanner::Scanner;
+use shutdown::Shutdown;
+use stats::HISTORY_LENGTH;
+use stats::StatsMeter;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use video::VideoRecorder;
 
 /// Initial window dimensions.
 const INITIAL_WINDOW_DIMENSIONS : [u32; 2] = [600, 600];
@@ -19,7 +42,215 @@ const INITIAL_WINDOW_DIMENSIONS : [u32; 2] = [600, 600];
 /// Not completely black so that laser blanking can be seen.
 const BG_COLOR : [f32; 4] = [0.1, 0.1, 0.1, 1.0];
 
-pub fn gl_window(pipeline: Arc<Pipeline>, runtime_opts: &RuntimeOpts) {
+/// RGBA color of the bar flashed at the top of the window on underflow.
+const UNDERFLOW_COLOR : [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+/// RGBA color of the lines between DACs when several are shown.
+const DIVIDER_COLOR : [f32; 4] = [0.4, 0.4, 0.4, 1.0];
+
+/// RGBA color of blanked points when blanking is shown.
+const BLANKING_COLOR : [f32; 4] = [0.2, 0.3, 0.6, 1.0];
+
+/// RGBA color of the marks on points with control bits set.
+const CONTROL_COLOR : [f32; 4] = [1.0, 0.8, 0.0, 1.0];
+
+/// RGBA color of the marks on points that change the point rate.
+const RATE_CHANGE_COLOR : [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+
+/// Size of the marks on points with control bits set.
+const CONTROL_MARK_SIZE : f64 = 6.0;
+
+/// RGBA color of the grid lines.
+const GRID_COLOR : [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+
+/// RGBA color of the grid lines through the origin, and the edge of the
+/// coordinate space.
+const AXIS_COLOR : [f32; 4] = [0.45, 0.45, 0.45, 1.0];
+
+/// RGBA color of the grid labels.
+const GRID_LABEL_COLOR : [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+
+/// Height of the grid labels, in pixels.
+const GRID_LABEL_HEIGHT : f64 = 7.0;
+
+/// Grid lines are placed at the finest power of two ILDA units that keeps
+/// them at least this many pixels apart.
+const GRID_MIN_SPACING : f64 = 48.0;
+
+/// RGBA color of the coordinate readout under the cursor.
+const READOUT_COLOR : [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+
+/// Height of the coordinate readout, in pixels.
+const READOUT_HEIGHT : f64 = 9.0;
+
+/// RGBA color behind the statistics HUD.
+const HUD_BG_COLOR : [f32; 4] = [0.0, 0.0, 0.0, 0.7];
+
+/// RGBA color of the statistics HUD's text.
+const HUD_TEXT_COLOR : [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+
+/// RGBA color of the buffer fullness sparkline.
+const SPARKLINE_COLOR : [f32; 4] = [0.3, 0.9, 0.4, 1.0];
+
+/// Height of the statistics HUD's text, in pixels.
+const HUD_TEXT_HEIGHT : f64 = 8.0;
+
+/// Height of each line of the statistics HUD, in pixels.
+const HUD_LINE_HEIGHT : f64 = 13.0;
+
+/// Size of the buffer fullness sparkline, in pixels.
+const SPARKLINE_SIZE : [f64; 2] = [180.0, 30.0];
+
+/// How much each step of the mouse wheel zooms.
+const ZOOM_STEP : f64 = 1.25;
+
+/// Milliseconds between frames drawn offscreen, about a display's refresh.
+const OFFSCREEN_FRAME_MS : u64 = 16;
+
+/// How long the underflow bar is shown for.
+const UNDERFLOW_FLASH_MS : u64 = 500;
+
+/// How the points played are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+  /// A dot per point, for checking point density.
+  Dots,
+  /// Line segments between consecutive points, like the scanning beam.
+  Lines,
+}
+
+impl FromStr for RenderMode {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<RenderMode, ()> {
+    match s {
+      "dots" => Ok(RenderMode::Dots),
+      "lines" => Ok(RenderMode::Lines),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Which channel of the points is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+  /// The beam color.
+  Color,
+  /// The intensity channel, in grayscale.
+  Intensity,
+  /// The first user channel, in grayscale.
+  User1,
+  /// The second user channel, in grayscale.
+  User2,
+}
+
+impl Channel {
+  /// The channel after this one, for cycling through them.
+  pub fn next(self) -> Channel {
+    match self {
+      Channel::Color => Channel::Intensity,
+      Channel::Intensity => Channel::User1,
+      Channel::User1 => Channel::User2,
+      Channel::User2 => Channel::Color,
+    }
+  }
+}
+
+impl FromStr for Channel {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<Channel, ()> {
+    match s {
+      "color" => Ok(Channel::Color),
+      "i" => Ok(Channel::Intensity),
+      "u1" => Ok(Channel::User1),
+      "u2" => Ok(Channel::User2),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Debugging aids for the points drawn, and the part of the coordinate
+/// space shown, changed from the keyboard and mouse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Overlay {
+  /// Draw blanked points in a dim color, and mark points with control bits.
+  pub blanking: bool,
+  /// Channel of the points drawn.
+  pub channel: Channel,
+  /// Draw a grid labeled in ILDA units.
+  pub grid: bool,
+  /// Zoom and pan, the same for every DAC.
+  pub camera: Camera,
+  /// Show each DAC's connection, playback and traffic statistics.
+  pub hud: bool,
+}
+
+impl Overlay {
+  /// Initial overlay, from the command line.
+  pub fn new(runtime_opts: &RuntimeOpts) -> Overlay {
+    Overlay {
+      blanking: runtime_opts.show_blanking,
+      channel: runtime_opts.channel,
+      grid: runtime_opts.show_grid,
+      camera: Camera::new(),
+      hud: runtime_opts.show_hud,
+    }
+  }
+}
+
+/// What is drawn for a DAC: its recent points, where its scanners would
+/// have traced them.
+pub struct DacView {
+  persistence: Persistence,
+  scanner: Option<Scanner>,
+  meter: StatsMeter,
+}
+
+impl DacView {
+  /// CTOR.
+  pub fn new(runtime_opts: &RuntimeOpts) -> DacView {
+    DacView {
+      persistence: Persistence::new(runtime_opts.persistence),
+      scanner: runtime_opts.scanner_kpps.map(|kpps| {
+        Scanner::new(kpps, runtime_opts.scanner_max_step,
+                     runtime_opts.scanner_damping)
+      }),
+      meter: StatsMeter::new(),
+    }
+  }
+
+  /// Take the points played since the last update. Points are kept on screen
+  /// while playback is paused.
+  pub fn update(&mut self, pipeline: &Pipeline, now: Instant) {
+    if self.meter.is_due(now) {
+      if let Ok(stats) = pipeline.stats() {
+        let capacity = pipeline.buffer_capacity().max(1);
+        let fullness = pipeline.queue_size().unwrap_or(0) as f32
+            / capacity as f32;
+        self.meter.sample(now, stats, fullness);
+      }
+    }
+
+    if pipeline.is_paused() {
+      return;
+    }
+
+    let mut points = pipeline.dequeue().unwrap_or(Vec::new()); // TODO
+
+    if let Some(ref mut scanner) = self.scanner {
+      let point_rate = pipeline.point_rate().unwrap_or(0);
+      points = scanner.scan(&points, point_rate);
+    }
+
+    self.persistence.push(now, points);
+  }
+}
+
+/// Show the points played by each DAC until the window is closed or the
+/// emulator is shut down.
+pub fn gl_window(pipelines: Vec<Arc<Pipeline>>, runtime_opts: &RuntimeOpts,
+                 shutdown: &Shutdown) {
   let opengl = OpenGL::V3_2;
   let ref mut window: GliumWindow =
       WindowSettings::new("EtherDream Emulator", INITIAL_WINDOW_DIMENSIONS)
@@ -29,67 +260,653 @@ pub fn gl_window(pipeline: Arc<Pipeline>, runtime_opts: &RuntimeOpts) {
         .unwrap();
 
   let mut g2d = Glium2d::new(opengl, window);
+
+  let mut views : Vec<DacView> = pipelines.iter()
+      .map(|_| DacView::new(runtime_opts))
+      .collect();
+
+  let mut overlay = Overlay::new(runtime_opts);
+  let mut size = INITIAL_WINDOW_DIMENSIONS;
+  let mut snapshots = 0;
+  let mut video = open_video(runtime_opts);
+
+  // Mouse position in the window, if it's over the window.
+  let mut cursor : Option<[f64; 2]> = None;
+  let mut dragging = false;
+
   while let Some(e) = window.next() {
+    if shutdown.is_stopped() {
+      return;
+    }
+
+    let tiles = tile_layout(pipelines.len(), size[0], size[1]);
+
+    if let Some(position) = e.mouse_cursor_args() {
+      if let (true, Some(last)) = (dragging, cursor) {
+        if let Some(tile) = tile_at(&tiles, last) {
+          overlay.camera.pan(position[0] - last[0], position[1] - last[1],
+                             tile[2], tile[3]);
+        }
+      }
+      cursor = Some(position);
+    }
+
+    if let Some(false) = e.cursor_args() {
+      cursor = None;
+      dragging = false;
+    }
+
+    if let Some(scroll) = e.mouse_scroll_args() {
+      let tile = cursor.and_then(|c| tile_at(&tiles, c).map(|t| (c, t)));
+
+      if let Some((position, tile)) = tile {
+        overlay.camera.zoom_at(ZOOM_STEP.powf(scroll[1]),
+                               position[0] - tile[0], position[1] - tile[1],
+                               tile[2], tile[3]);
+      }
+    }
+
+    if let Some(Button::Mouse(MouseButton::Left)) = e.release_args() {
+      dragging = false;
+    }
+
+    match e.press_args() {
+      Some(Button::Mouse(MouseButton::Left)) => {
+        dragging = true;
+      },
+      Some(Button::Keyboard(Key::H)) => {
+        overlay.hud = !overlay.hud;
+      },
+      Some(Button::Keyboard(Key::G)) => {
+        overlay.grid = !overlay.grid;
+      },
+      Some(Button::Keyboard(Key::R)) => {
+        overlay.camera = Camera::new();
+      },
+      Some(Button::Keyboard(Key::Space)) => {
+        // Only replays can be paused; a client would see its DAC stall.
+        if runtime_opts.replay_path.is_some() {
+          for pipeline in pipelines.iter() {
+            pipeline.set_paused(!pipeline.is_paused());
+          }
+        }
+      },
+      Some(Button::Keyboard(Key::B)) => {
+        overlay.blanking = !overlay.blanking;
+      },
+      Some(Button::Keyboard(Key::C)) => {
+        overlay.channel = overlay.channel.next();
+      },
+      Some(Button::Keyboard(Key::S)) => {
+        if let Some(ref path) = runtime_opts.snapshot_path {
+          snapshots += 1;
+          let path = numbered_path(path, snapshots);
+          save_snapshot(&path, &pipelines, &views, size, runtime_opts,
+                        overlay);
+        }
+      },
+      _ => {},
+    }
+
     if let Some(args) = e.render_args() {
+      let now = Instant::now();
+
+      for (view, pipeline) in views.iter_mut().zip(pipelines.iter()) {
+        view.update(pipeline, now);
+      }
+
+      record_video(&mut video, &pipelines, &views, runtime_opts, overlay);
+
+      size = [args.width, args.height];
 
       let mut frame = window.draw();
       g2d.draw(&mut frame, args.viewport(), |ctx, gfx| {
-        // Draw background color
-        Rectangle::new(BG_COLOR)
-          .draw([0.0, 0.0, args.width as f64, args.height as f64],
-                &ctx.draw_state,
-                ctx.transform,
+        draw_frame(&pipelines, &views, args.width, args.height, runtime_opts,
+                   overlay, cursor, &ctx, gfx);
+      });
+
+      frame.finish().unwrap();
+    }
+  }
+
+  println!("Window closed.");
+  shutdown.stop();
+}
+
+/// Draw what the window would show in memory, without a GPU or display,
+/// until the emulator is shut down. Snapshots are saved periodically if
+/// asked for, and on shutdown.
+pub fn offscreen(pipelines: Vec<Arc<Pipeline>>, runtime_opts: &RuntimeOpts,
+                 shutdown: &Shutdown) {
+  let mut video = open_video(runtime_opts);
+
+  if runtime_opts.snapshot_path.is_none() && video.is_none() {
+    // Nothing to draw for.
+    while !shutdown.wait(Duration::from_secs(1)) {}
+    return;
+  }
+
+  let mut views : Vec<DacView> = pipelines.iter()
+      .map(|_| DacView::new(runtime_opts))
+      .collect();
+
+  let overlay = Overlay::new(runtime_opts);
+  let size = runtime_opts.image_size;
+  let mut last_snapshot = Instant::now();
+  let mut snapshots = 0;
+
+  while !shutdown.wait(Duration::from_millis(OFFSCREEN_FRAME_MS)) {
+    let now = Instant::now();
+
+    for (view, pipeline) in views.iter_mut().zip(pipelines.iter()) {
+      view.update(pipeline, now);
+    }
+
+    record_video(&mut video, &pipelines, &views, runtime_opts, overlay);
+
+    if let (Some(path), Some(interval)) = (runtime_opts.snapshot_path.as_ref(),
+                                           runtime_opts.snapshot_interval) {
+      if now.duration_since(last_snapshot) >= interval {
+        last_snapshot = now;
+        snapshots += 1;
+        save_snapshot(&numbered_path(path, snapshots), &pipelines, &views,
+                      size, runtime_opts, overlay);
+      }
+    }
+  }
+
+  if let Some(ref path) = runtime_opts.snapshot_path {
+    save_snapshot(path, &pipelines, &views, size, runtime_opts, overlay);
+  }
+}
+
+/// Start recording video, if asked for.
+fn open_video(runtime_opts: &RuntimeOpts) -> Option<VideoRecorder> {
+  runtime_opts.video_path.as_ref().map(|path| {
+    println!("Recording video to {}", path);
+    VideoRecorder::create(path, runtime_opts.video_fps,
+                          runtime_opts.image_size)
+        .expect("Could not create video file.")
+  })
+}
+
+/// Record the frames that have come due, all of the current picture. Frames
+/// are repeated if drawing falls behind, so the video keeps time.
+fn record_video(video: &mut Option<VideoRecorder>,
+                pipelines: &[Arc<Pipeline>], views: &[DacView],
+                runtime_opts: &RuntimeOpts, overlay: Overlay) {
+  let failed = match *video {
+    None => false,
+    Some(ref mut video) => {
+      // NB: Playback time stands still while paused, so the video does too.
+      let position = pipelines.iter()
+          .filter_map(|p| p.stats().ok())
+          .map(|stats| stats.time_played)
+          .max()
+          .unwrap_or(Duration::from_secs(0));
+
+      let due = video.frames_due(position);
+
+      if due == 0 {
+        return;
+      }
+
+      let canvas = rasterize(pipelines, views, runtime_opts.image_size,
+                             runtime_opts, overlay);
+
+      (0 .. due).any(|_| video.write_frame(&canvas).is_err())
+    },
+  };
+
+  if failed {
+    println!("Video recording failed; no longer recording.");
+    *video = None;
+  }
+}
+
+/// Draw the window contents into an image.
+pub fn rasterize(pipelines: &[Arc<Pipeline>], views: &[DacView], size: [u32; 2],
+                 runtime_opts: &RuntimeOpts, overlay: Overlay) -> Canvas {
+  let mut canvas = Canvas::new(size[0], size[1]);
+  let ctx = Context::new_abs(size[0] as f64, size[1] as f64);
+
+  draw_frame(pipelines, views, size[0], size[1], runtime_opts, overlay, None,
+             &ctx, &mut canvas);
+  canvas
+}
+
+fn save_snapshot(path: &str, pipelines: &[Arc<Pipeline>], views: &[DacView],
+                 size: [u32; 2], runtime_opts: &RuntimeOpts,
+                 overlay: Overlay) {
+  let canvas = rasterize(pipelines, views, size, runtime_opts, overlay);
+
+  match canvas.write_png(path) {
+    Ok(_) => println!("Saved snapshot {}", path),
+    Err(e) => println!("Could not save snapshot {}: {}", path, e),
+  }
+}
+
+/// Draw every DAC's points, tiled, over the background, with a readout of
+/// the coordinate under the cursor if any.
+fn draw_frame<G: Graphics>(pipelines: &[Arc<Pipeline>], views: &[DacView],
+                           width: u32, height: u32,
+                           runtime_opts: &RuntimeOpts, overlay: Overlay,
+                           cursor: Option<[f64; 2]>, ctx: &Context,
+                           gfx: &mut G) {
+  // Draw background color
+  Rectangle::new(BG_COLOR)
+    .draw([0.0, 0.0, width as f64, height as f64],
+          &ctx.draw_state,
+          ctx.transform,
+          gfx);
+
+  let tiles = tile_layout(pipelines.len(), width, height);
+
+  for (i, tile) in tiles.iter().enumerate() {
+    let (left, top, tile_width, tile_height) = (tile[0], tile[1], tile[2],
+                                                tile[3]);
+
+    // Keep zoomed in points within the tile. NB: Scissor rectangles are
+    // measured from the bottom of the window.
+    let mut tile_ctx = ctx.trans(left, top);
+    tile_ctx.draw_state = ctx.draw_state.scissor([
+      left.round() as u32,
+      (height as f64 - top - tile_height).round().max(0.0) as u32,
+      tile_width.round() as u32,
+      tile_height.round() as u32,
+    ]);
+
+    if overlay.grid {
+      draw_grid(overlay.camera, tile_width, tile_height, &tile_ctx, gfx);
+    }
+
+    draw_dac(&pipelines[i], &views[i].persistence, tile_width, tile_height,
+             runtime_opts, overlay, &tile_ctx, gfx);
+
+    if overlay.hud {
+      draw_hud(&pipelines[i], &views[i].meter, &tile_ctx, gfx);
+    }
+
+    if tiles.len() > 1 {
+      Rectangle::new_border(DIVIDER_COLOR, 0.5)
+        .draw(*tile, &ctx.draw_state, ctx.transform, gfx);
+    }
+  }
+
+  if let Some(position) = cursor {
+    if let Some(tile) = tile_at(&tiles, position) {
+      let camera = overlay.camera;
+      let coordinate = camera.to_ilda(position[0] - tile[0],
+                                      position[1] - tile[1], tile[2], tile[3]);
+
+      let mut readout = format!("x {}  y {}", coordinate[0].round() as i32,
+                                coordinate[1].round() as i32);
+      if camera.zoom != 1.0 {
+        readout.push_str(&format!("  zoom {:.1}x", camera.zoom));
+      }
+
+      draw_text(&readout,
+                [tile[0] + 6.0, tile[1] + tile[3] - READOUT_HEIGHT - 6.0],
+                READOUT_HEIGHT, READOUT_COLOR, &ctx.draw_state, ctx.transform,
                 gfx);
+    }
+  }
+}
+
+/// Draw the points played by a single DAC into its tile of the window.
+fn draw_dac<G: Graphics>(pipeline: &Pipeline, persistence: &Persistence,
+                         width: f64, height: f64, runtime_opts: &RuntimeOpts,
+                         overlay: Overlay, ctx: &Context, gfx: &mut G) {
+  for trace in persistence.traces() {
+    let fade = persistence.fade(trace);
+    draw_trace(trace, fade, width, height, runtime_opts, overlay, ctx, gfx);
+  }
 
-        let result = pipeline.dequeue(1_000);
-        let points = match result {
-          Err(_) => Vec::new(), // TODO
-          Ok(points) => points,
-        };
-
-        for point in points {
-          let x = map_x(point.x, args.width);
-          let y = map_y(point.y, args.height);
-          let r = map_color(point.r);
-          let g = map_color(point.g);
-          let b = map_color(point.b);
-
-          Ellipse::new([r, g, b, 1.0])
-            .draw([
-                  // Position
-                  x,
-                  y,
-                  // Size of shape.
-                  runtime_opts.point_size,
-                  runtime_opts.point_size,
-            ],
-            &ctx.draw_state, ctx.transform, gfx);
+  if recent_underflow(pipeline) {
+    Rectangle::new(UNDERFLOW_COLOR)
+      .draw([0.0, 0.0, width, 4.0], &ctx.draw_state, ctx.transform, gfx);
+  }
+}
+
+/// Draw a batch of points, faded by `fade`, into a tile of the given size.
+fn draw_trace<G: Graphics>(trace: &Trace, fade: f32, width: f64, height: f64,
+                           runtime_opts: &RuntimeOpts, overlay: Overlay,
+                           ctx: &Context, gfx: &mut G) {
+  let brightness = runtime_opts.brightness;
+  let color = |point: &Point| {
+    let mut color = point_color(point, overlay, brightness);
+    color[3] *= fade;
+    color
+  };
+  let position = |point: &Point| {
+    overlay.camera.to_tile(point.x as f64, point.y as f64, width, height)
+  };
+
+  match runtime_opts.render_mode {
+    RenderMode::Dots => {
+      for point in trace.points.iter() {
+        let p = position(point);
+        let (x, y) = (p[0], p[1]);
+
+        Ellipse::new(color(point))
+          .draw([
+                // Position
+                x,
+                y,
+                // Size of shape.
+                runtime_opts.point_size,
+                runtime_opts.point_size,
+          ],
+          &ctx.draw_state, ctx.transform, gfx);
+      }
+    },
+    RenderMode::Lines => {
+      let radius = runtime_opts.line_width / 2.0;
+      let mut from = trace.previous.as_ref();
+
+      for point in trace.points.iter() {
+        let blanked = point.r == 0 && point.g == 0 && point.b == 0;
+
+        if blanked && !overlay.blanking {
+          from = Some(point); // The beam is off while moving here.
+          continue;
         }
-      });
 
-      frame.finish().unwrap();
+        // NB: A segment takes the color of the point it ends on, since
+        // that's the color the DAC outputs while moving there.
+        let color = color(point);
+        let p = position(point);
+        let (x, y) = (p[0], p[1]);
+
+        match from {
+          Some(from) if from.x != point.x || from.y != point.y => {
+            let from = position(from);
+
+            Line::new_round(color, radius)
+              .draw([from[0], from[1], x, y], &ctx.draw_state, ctx.transform,
+                    gfx);
+          },
+          _ => {
+            // The beam is dwelling; a zero length line wouldn't show.
+            Ellipse::new(color)
+              .draw([x - radius, y - radius, radius * 2.0, radius * 2.0],
+                    &ctx.draw_state, ctx.transform, gfx);
+          },
+        }
+
+        from = Some(point);
+      }
+    },
+  }
+
+  if overlay.blanking {
+    let size = CONTROL_MARK_SIZE;
+
+    for point in trace.points.iter().filter(|p| p.control != 0) {
+      let p = position(point);
+      let (x, y) = (p[0], p[1]);
+
+      let mut color = if point.control & POINT_CONTROL_RATE_CHANGE != 0 {
+        RATE_CHANGE_COLOR
+      } else {
+        CONTROL_COLOR
+      };
+      color[3] *= fade;
+
+      Rectangle::new_border(color, 0.5)
+        .draw([x - size / 2.0, y - size / 2.0, size, size],
+              &ctx.draw_state, ctx.transform, gfx);
     }
   }
+}
+
+/// Draw grid lines across the coordinate space of a tile, labeled in ILDA
+/// units, and its edge.
+fn draw_grid<G: Graphics>(camera: Camera, width: f64, height: f64,
+                          ctx: &Context, gfx: &mut G) {
+  let step = grid_step(camera.scale(width, height)[0]);
+  let (min, max) = (limit::MIN_X as f64, limit::MAX_X as f64);
+
+  let top_left = camera.to_ilda(0.0, 0.0, width, height);
+  let bottom_right = camera.to_ilda(width, height, width, height);
+
+  // Boxes of the x labels, which the y labels must stay clear of.
+  let mut x_labels = Vec::new();
+
+  let mut x = (top_left[0].max(min) / step).ceil() * step;
+  while x <= bottom_right[0].min(max) {
+    let color = if x == 0.0 { AXIS_COLOR } else { GRID_COLOR };
+    let from = camera.to_tile(x, max, width, height);
+    let to = camera.to_tile(x, min, width, height);
+
+    Line::new(color, 0.5)
+      .draw([from[0], from[1], to[0], to[1]], &ctx.draw_state, ctx.transform,
+            gfx);
+    let label = format!("{}", x);
+    let position = [from[0] + 3.0, from[1].max(0.0) + 3.0];
+    draw_text(&label, position, GRID_LABEL_HEIGHT, GRID_LABEL_COLOR,
+              &ctx.draw_state, ctx.transform, gfx);
+
+    x_labels.push([position[0], position[1],
+                   text_width(&label, GRID_LABEL_HEIGHT), GRID_LABEL_HEIGHT]);
 
-  println!("Window closed. Terminating process.");
-  process::exit(0);
+    x += step;
+  }
+
+  let mut y = (bottom_right[1].max(min) / step).ceil() * step;
+  while y <= top_left[1].min(max) {
+    let color = if y == 0.0 { AXIS_COLOR } else { GRID_COLOR };
+    let from = camera.to_tile(min, y, width, height);
+    let to = camera.to_tile(max, y, width, height);
+
+    Line::new(color, 0.5)
+      .draw([from[0], from[1], to[0], to[1]], &ctx.draw_state, ctx.transform,
+            gfx);
+
+    let label = format!("{}", y);
+    let position = [from[0].max(0.0) + 3.0, from[1] - GRID_LABEL_HEIGHT - 3.0];
+    let label_box = [position[0], position[1],
+                     text_width(&label, GRID_LABEL_HEIGHT), GRID_LABEL_HEIGHT];
+
+    if !x_labels.iter().any(|&b| overlaps(b, label_box)) {
+      draw_text(&label, position, GRID_LABEL_HEIGHT, GRID_LABEL_COLOR,
+                &ctx.draw_state, ctx.transform, gfx);
+    }
+
+    y += step;
+  }
+
+  let corner = camera.to_tile(min, max, width, height);
+  let far_corner = camera.to_tile(max, min, width, height);
+
+  Rectangle::new_border(AXIS_COLOR, 0.5)
+    .draw([corner[0], corner[1], far_corner[0] - corner[0],
+           far_corner[1] - corner[1]],
+          &ctx.draw_state, ctx.transform, gfx);
 }
 
-// FIXME: This is abhorrent.
-#[inline]
-pub fn map_x(x: i16, width: u32) -> f64 {
-  let tx = (x as i32).saturating_add(limit::MAX_X as i32);
-  let scale = width as f64 / limit::WIDTH as f64;
-  tx as f64 * scale
+/// Whether two [x, y, width, height] boxes overlap.
+fn overlaps(a: [f64; 4], b: [f64; 4]) -> bool {
+  a[0] < b[0] + b[2] && b[0] < a[0] + a[2]
+      && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
 }
 
-// FIXME: This is abhorrent.
-#[inline]
-pub fn map_y(y: i16, height: u32) -> f64 {
-  // NB: Have to invert y since the vertical coordinate system transforms.
-  let ty = ((y * -1) as i32).saturating_add(limit::MAX_Y as i32);
-  let scale = height as f64 / limit::HEIGHT as f64;
-  ty as f64 * scale
+/// Draw a DAC's connection, playback and traffic statistics, and a
+/// sparkline of its buffer fullness, at the top left of its tile.
+fn draw_hud<G: Graphics>(pipeline: &Pipeline, meter: &StatsMeter,
+                         ctx: &Context, gfx: &mut G) {
+  let status = match pipeline.status() {
+    Err(_) => return,
+    Ok(status) => status,
+  };
+
+  let stats = match meter.latest() {
+    None => return,
+    Some(stats) => stats,
+  };
+
+  let rates = meter.rates();
+
+  let lines = [
+    connection_state(&status, stats.connected).to_string(),
+    playback_state(&status, pipeline.is_paused()),
+    format!("RECEIVED {:.0} PTS/S", rates.points_received),
+    format!("PLAYED {:.0} PTS/S", rates.points_played),
+    format!("PACKETS {:.0}/S  NAKS {}", rates.packets, stats.naks),
+    format!("UNDERFLOWS {}", stats.underflows),
+    format!("BUFFER {}/{}", status.buffer_fullness,
+            pipeline.buffer_capacity()),
+  ];
+
+  let (left, top) = (8.0, 10.0);
+  let padding = 6.0;
+  let sparkline_top = top + lines.len() as f64 * HUD_LINE_HEIGHT;
+
+  Rectangle::new(HUD_BG_COLOR)
+    .draw([left - padding, top - padding, SPARKLINE_SIZE[0] + padding * 2.0,
+           sparkline_top + SPARKLINE_SIZE[1] + padding * 2.0 - top],
+          &ctx.draw_state, ctx.transform, gfx);
+
+  for (i, line) in lines.iter().enumerate() {
+    draw_text(line, [left, top + i as f64 * HUD_LINE_HEIGHT], HUD_TEXT_HEIGHT,
+              HUD_TEXT_COLOR, &ctx.draw_state, ctx.transform, gfx);
+  }
+
+  let (width, height) = (SPARKLINE_SIZE[0], SPARKLINE_SIZE[1]);
+
+  Rectangle::new_border(DIVIDER_COLOR, 0.5)
+    .draw([left, sparkline_top, width, height], &ctx.draw_state,
+          ctx.transform, gfx);
+
+  // The most recent sample is at the right edge.
+  let fullness = meter.fullness();
+  let step = width / (HISTORY_LENGTH - 1) as f64;
+  let offset = HISTORY_LENGTH.saturating_sub(fullness.len());
+  let position = |i: usize, level: f32| {
+    [left + (i + offset) as f64 * step,
+     sparkline_top + height - level as f64 * height]
+  };
+
+  let line = Line::new(SPARKLINE_COLOR, 0.5);
+
+  for i in 1 .. fullness.len() {
+    let from = position(i - 1, fullness[i - 1]);
+    let to = position(i, fullness[i]);
+    line.draw([from[0], from[1], to[0], to[1]], &ctx.draw_state,
+              ctx.transform, gfx);
+  }
+}
+
+/// Who is feeding the DAC, as shown in the HUD.
+pub fn connection_state(status: &DacStatus, connected: bool) -> &'static str {
+  match status.source {
+    SOURCE_ILDA => "ILDA FILES",
+    SOURCE_GENERATOR => "GENERATOR",
+    _ if connected => "CLIENT CONNECTED",
+    _ => "NO CLIENT",
+  }
+}
+
+/// What the DAC is doing, as shown in the HUD.
+pub fn playback_state(status: &DacStatus, paused: bool) -> String {
+  if status.light_engine_state == LIGHT_ENGINE_ESTOP {
+    return "E-STOP".to_string();
+  }
+
+  match status.playback_state {
+    PLAYBACK_IDLE => "IDLE".to_string(),
+    PLAYBACK_PREPARED => "PREPARED".to_string(),
+    PLAYBACK_PLAYING if paused => {
+      format!("PAUSED AT {} PTS/S", status.point_rate)
+    },
+    PLAYBACK_PLAYING => format!("PLAYING AT {} PTS/S", status.point_rate),
+    _ => "UNKNOWN".to_string(),
+  }
+}
+
+/// Spacing of grid lines, in ILDA units, at the given pixels per unit.
+pub fn grid_step(scale: f64) -> f64 {
+  let mut step = 1.0;
+
+  while step * scale < GRID_MIN_SPACING && step < limit::WIDTH as f64 {
+    step *= 2.0;
+  }
+
+  step
+}
+
+/// The tile containing a position in the window, if any.
+fn tile_at(tiles: &[[f64; 4]], position: [f64; 2]) -> Option<[f64; 4]> {
+  tiles.iter().cloned().find(|tile| {
+    position[0] >= tile[0] && position[0] < tile[0] + tile[2]
+        && position[1] >= tile[1] && position[1] < tile[1] + tile[3]
+  })
+}
+
+/// Split the window into a near-square grid with one tile per DAC, in
+/// left-to-right, top-to-bottom order. Tiles are [x, y, width, height].
+pub fn tile_layout(count: usize, width: u32, height: u32) -> Vec<[f64; 4]> {
+  let count = count.max(1);
+  let columns = (count as f64).sqrt().ceil() as usize;
+  let rows = (count + columns - 1) / columns;
+
+  let tile_width = width as f64 / columns as f64;
+  let tile_height = height as f64 / rows as f64;
+
+  (0 .. count).map(|i| {
+    let column = i % columns;
+    let row = i / columns;
+    [column as f64 * tile_width, row as f64 * tile_height,
+     tile_width, tile_height]
+  }).collect()
+}
+
+/// Whether playback underflowed recently enough to still be flagged.
+fn recent_underflow(pipeline: &Pipeline) -> bool {
+  match pipeline.last_underflow() {
+    Ok(Some(when)) =>
+      Instant::now().duration_since(when)
+          < Duration::from_millis(UNDERFLOW_FLASH_MS),
+    _ => false,
+  }
+}
+
+/// Add a sequence number to a file name, eg. `snapshot-00001.png`.
+pub fn numbered_path(path: &str, number: usize) -> String {
+  let path = Path::new(path);
+  let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+  let name = match path.extension().and_then(|s| s.to_str()) {
+    None => format!("{}-{:05}", stem, number),
+    Some(extension) => format!("{}-{:05}.{}", stem, number, extension),
+  };
+
+  path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// RGBA color a point is drawn in, given the overlay.
+pub fn point_color(point: &Point, overlay: Overlay, brightness: f64)
+                   -> [f32; 4] {
+  let blanked = point.r == 0 && point.g == 0 && point.b == 0;
+
+  if overlay.blanking && blanked {
+    return BLANKING_COLOR;
+  }
+
+  let level = match overlay.channel {
+    Channel::Color => return beam_color(point, brightness),
+    Channel::Intensity => point.i,
+    Channel::User1 => point.u1,
+    Channel::User2 => point.u2,
+  };
+
+  let gray = (map_color(level) * brightness as f32).min(1.0);
+  [gray, gray, gray, 1.0]
+}
+
+/// RGBA color of the beam at a point, scaled by the brightness.
+pub fn beam_color(point: &Point, brightness: f64) -> [f32; 4] {
+  let scale = |c: u16| (map_color(c) * brightness as f32).min(1.0);
+  [scale(point.r), scale(point.g), scale(point.b), 1.0]
 }
 
 /// Convert color space from ILDA to float.
@@ -98,3 +915,166 @@ pub fn map_color(c: u16) -> f32 {
   c as f32 / 65535.0
 }
 
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use protocol::DacStatus;
+  use std::sync::RwLock;
+
+  #[test]
+  fn test_tile_layout() {
+    assert_eq!(tile_layout(1, 600, 600), vec![[0.0, 0.0, 600.0, 600.0]]);
+
+    assert_eq!(tile_layout(2, 600, 600), vec![
+      [0.0, 0.0, 300.0, 600.0],
+      [300.0, 0.0, 300.0, 600.0],
+    ]);
+
+    // Three DACs fill a 2x2 grid, leaving the last tile empty.
+    assert_eq!(tile_layout(3, 600, 600), vec![
+      [0.0, 0.0, 300.0, 300.0],
+      [300.0, 0.0, 300.0, 300.0],
+      [0.0, 300.0, 300.0, 300.0],
+    ]);
+  }
+
+  #[test]
+  fn test_grid_step() {
+    // 600 pixels across the whole coordinate space.
+    assert_eq!(grid_step(600.0 / 65535.0), 8192.0);
+    assert_eq!(grid_step(600.0 / 65535.0 * 64.0), 128.0);
+    assert_eq!(grid_step(100.0), 1.0);
+  }
+
+  #[test]
+  fn test_tile_at() {
+    let tiles = tile_layout(2, 600, 600);
+
+    assert_eq!(tile_at(&tiles, [100.0, 100.0]), Some(tiles[0]));
+    assert_eq!(tile_at(&tiles, [300.0, 599.0]), Some(tiles[1]));
+    assert_eq!(tile_at(&tiles, [600.0, 100.0]), None);
+  }
+
+  #[test]
+  fn test_hud_states() {
+    let mut status = DacStatus::empty();
+
+    assert_eq!(connection_state(&status, false), "NO CLIENT");
+    assert_eq!(connection_state(&status, true), "CLIENT CONNECTED");
+    assert_eq!(playback_state(&status, false), "IDLE");
+
+    status.playback_state = PLAYBACK_PLAYING;
+    status.point_rate = 30_000;
+    assert_eq!(playback_state(&status, false), "PLAYING AT 30000 PTS/S");
+    assert_eq!(playback_state(&status, true), "PAUSED AT 30000 PTS/S");
+
+    status.source = SOURCE_ILDA;
+    assert_eq!(connection_state(&status, false), "ILDA FILES");
+  }
+
+  #[test]
+  fn test_overlaps() {
+    let label = [10.0, 10.0, 20.0, 7.0];
+
+    assert!(overlaps(label, [25.0, 15.0, 10.0, 7.0]));
+    assert!(!overlaps(label, [30.0, 10.0, 10.0, 7.0]));
+    assert!(!overlaps(label, [10.0, 17.0, 20.0, 7.0]));
+  }
+
+  #[test]
+  fn test_numbered_path() {
+    assert_eq!(numbered_path("out/snap.png", 7), "out/snap-00007.png");
+    assert_eq!(numbered_path("snap", 12), "snap-00012");
+  }
+
+  #[test]
+  fn test_rasterize() {
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Arc::new(Pipeline::new(status, 10));
+
+    // A dwelling beam is drawn centered on its point.
+    let opts = RuntimeOpts {
+      render_mode: RenderMode::Lines,
+      ..RuntimeOpts::default()
+    };
+
+    let mut view = DacView::new(&opts);
+    view.persistence.push(Instant::now(), vec![Point {
+      control: 0, x: 0, y: 0, i: 65535, r: 65535, g: 0, b: 0, u1: 0, u2: 0,
+    }]);
+
+    let canvas = rasterize(&[pipeline], &[view], [9, 9], &opts,
+                           Overlay::new(&opts));
+    let rgb = canvas.to_rgb();
+
+    // The background, and the red dot in the middle.
+    assert_eq!(&rgb[0 .. 3], &[26, 26, 26]);
+    assert_eq!(rgb[(4 * 9 + 4) * 3], 255);
+  }
+
+  #[test]
+  fn test_rasterize_blanked_move() {
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Arc::new(Pipeline::new(status, 10));
+    let opts = RuntimeOpts {
+      render_mode: RenderMode::Lines,
+      ..RuntimeOpts::default()
+    };
+
+    // A lit point in the middle, then a blanked move to the right edge.
+    let mut view = DacView::new(&opts);
+    view.persistence.push(Instant::now(), vec![Point {
+      control: 0, x: 0, y: 0, i: 65535, r: 65535, g: 0, b: 0, u1: 0, u2: 0,
+    }, Point {
+      control: 0, x: 32767, y: 0, i: 0, r: 0, g: 0, b: 0, u1: 0, u2: 0,
+    }]);
+
+    let canvas = rasterize(&[pipeline], &[view], [9, 9], &opts,
+                           Overlay::new(&opts));
+    let rgb = canvas.to_rgb();
+
+    assert_eq!(rgb[(4 * 9 + 4) * 3], 255);
+    assert_eq!(&rgb[(4 * 9 + 7) * 3 .. (4 * 9 + 8) * 3], &[26, 26, 26]);
+  }
+
+  #[test]
+  fn test_beam_color() {
+    let point = Point {
+      control: 0, x: 0, y: 0, i: 65535, r: 65535, g: 13107, b: 0, u1: 0,
+      u2: 0,
+    };
+
+    assert_eq!(beam_color(&point, 1.0), [1.0, 0.2, 0.0, 1.0]);
+    assert_eq!(beam_color(&point, 0.5), [0.5, 0.1, 0.0, 1.0]);
+
+    // Brightening saturates rather than overflowing.
+    assert_eq!(beam_color(&point, 2.0), [1.0, 0.4, 0.0, 1.0]);
+  }
+
+  #[test]
+  fn test_point_color() {
+    let mut point = Point {
+      control: 0, x: 0, y: 0, i: 13107, r: 0, g: 0, b: 0, u1: 65535, u2: 0,
+    };
+
+    let mut overlay = Overlay {
+      blanking: false,
+      channel: Channel::Color,
+      grid: false,
+      camera: Camera::new(),
+      hud: false,
+    };
+    assert_eq!(point_color(&point, overlay, 1.0), [0.0, 0.0, 0.0, 1.0]);
+
+    overlay.blanking = true;
+    assert_eq!(point_color(&point, overlay, 1.0), BLANKING_COLOR);
+
+    point.r = 65535;
+    overlay.channel = Channel::Intensity;
+    assert_eq!(point_color(&point, overlay, 1.0), [0.2, 0.2, 0.2, 1.0]);
+
+    overlay.channel = overlay.channel.next();
+    assert_eq!(point_color(&point, overlay, 1.0), [1.0, 1.0, 1.0, 1.0]);
+  }
+}
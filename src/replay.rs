@@ -0,0 +1,315 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Plays a session file (see `session`) back through the virtual DACs, so
+//! a show can be reviewed without running the software that made it.
+//! Frames are fed to each DAC's pipeline at their original timing, scaled
+//! by a speed multiplier.
+
+use RuntimeOpts;
+use error::EmulatorError;
+use pipeline::Pipeline;
+use pipeline::parse_points;
+use protocol::DacStatus;
+use protocol::PLAYBACK_PLAYING;
+use protocol::POINT_CONTROL_RATE_CHANGE;
+use session::SessionFrame;
+use session::SessionReader;
+use shutdown::Shutdown;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often to check whether the next frame is due.
+const POLL_INTERVAL_MS : u64 = 1;
+
+/// Status and pipeline of a DAC being replayed to.
+pub type ReplayDac = (Arc<RwLock<DacStatus>>, Arc<Pipeline>);
+
+/// Replay the session named in the options into the DACs, one per DAC in
+/// the session, until it ends (or forever, when looping).
+pub fn replay_thread(opts: &RuntimeOpts, dacs: Vec<ReplayDac>,
+                     shutdown: &Shutdown) {
+  let path = match opts.replay_path {
+    None => return,
+    Some(ref path) => path,
+  };
+
+  loop {
+    match replay(path, opts.replay_speed, &dacs, shutdown) {
+      Err(EmulatorError::Stopped) => return,
+      Err(e) => {
+        println!("Replay failed: {}", e);
+        return;
+      },
+      Ok(_) => {},
+    }
+
+    if !opts.replay_loop {
+      println!("Replay finished.");
+      return;
+    }
+
+    for &(ref status, ref pipeline) in dacs.iter() {
+      let _r = reset(status, pipeline); // Ignore lock errors.
+    }
+  }
+}
+
+/// Replay a session once.
+pub fn replay(path: &str, speed: f64, dacs: &[ReplayDac],
+              shutdown: &Shutdown) -> Result<(), EmulatorError> {
+  let mut reader = SessionReader::open(path)?;
+  let mut clock = ReplayClock::new(speed, Instant::now());
+
+  // Point rate of the last frame played on each DAC.
+  let mut rates : Vec<Option<u32>> = dacs.iter().map(|_| None).collect();
+
+  while let Some(frame) = reader.next_frame()? {
+    // Wait for the frame to come due.
+    loop {
+      let paused = dacs.iter().any(|&(_, ref p)| p.is_paused());
+
+      if clock.update(Instant::now(), paused) >= frame.timestamp {
+        break;
+      }
+
+      if shutdown.wait(Duration::from_millis(POLL_INTERVAL_MS)) {
+        return Err(EmulatorError::Stopped);
+      }
+    }
+
+    let index = frame.dac as usize;
+
+    let (status, pipeline) = match dacs.get(index) {
+      None => continue,
+      Some(&(ref status, ref pipeline)) => (status, pipeline),
+    };
+
+    let rate = rates[index];
+    rates[index] = Some(frame.point_rate);
+
+    play_frame(frame, rate, speed, status, pipeline, shutdown)?;
+  }
+
+  Ok(())
+}
+
+/// Feed a frame to a DAC's pipeline, starting playback if it isn't playing
+/// and queueing a rate change if the frame's rate differs from the last.
+/// Point rates are scaled by the replay speed.
+fn play_frame(frame: SessionFrame, last_rate: Option<u32>, speed: f64,
+              status: &RwLock<DacStatus>, pipeline: &Pipeline,
+              shutdown: &Shutdown) -> Result<(), EmulatorError> {
+  let point_rate = scale_rate(frame.point_rate, speed);
+  let mut points = parse_points(frame.frame);
+
+  points.truncate(pipeline.buffer_capacity());
+
+  if points.is_empty() {
+    return Ok(());
+  }
+
+  loop {
+    {
+      // NB: Lock status before the queues, same as the network thread.
+      let mut status = status.write()?;
+
+      if status.playback_state != PLAYBACK_PLAYING {
+        pipeline.clear()?;
+        pipeline.enqueue_points(points)?;
+
+        status.playback_state = PLAYBACK_PLAYING;
+        status.playback_flags = 0;
+        status.point_count = 0;
+        status.point_rate = point_rate;
+        status.buffer_fullness = pipeline.queue_size()? as u16;
+        return Ok(());
+      }
+
+      let room = pipeline.buffer_capacity() - pipeline.queue_size()?;
+
+      let queued = if points.len() > room {
+        false
+      } else if last_rate == Some(frame.point_rate) {
+        true
+      } else {
+        // The new rate takes over after the frame's first point.
+        match pipeline.queue_rate_change(point_rate) {
+          Err(EmulatorError::PipelineFull) => false,
+          Err(e) => return Err(e),
+          Ok(_) => {
+            points[0].control |= POINT_CONTROL_RATE_CHANGE;
+            true
+          },
+        }
+      };
+
+      if queued {
+        pipeline.enqueue_points(points)?;
+        status.buffer_fullness = pipeline.queue_size()? as u16;
+        return Ok(());
+      }
+    }
+
+    // Wait for room for the points or the rate change, as a client would.
+    // Timing drift makes this more likely during replay.
+    if shutdown.wait(Duration::from_millis(POLL_INTERVAL_MS)) {
+      return Err(EmulatorError::Stopped);
+    }
+  }
+}
+
+/// Stop playback and empty the pipeline, ahead of replaying again.
+fn reset(status: &RwLock<DacStatus>, pipeline: &Pipeline)
+         -> Result<(), EmulatorError> {
+  let mut status = status.write()?;
+  pipeline.clear()?;
+  *status = DacStatus::empty();
+  Ok(())
+}
+
+#[inline]
+fn scale_rate(point_rate: u32, speed: f64) -> u32 {
+  (point_rate as f64 * speed).round().max(1.0) as u32
+}
+
+/// Position in the session, advancing with wall-clock time at the replay
+/// speed, except while paused.
+pub struct ReplayClock {
+  speed: f64,
+  /// Time into the session.
+  position: Duration,
+  /// When the position was last advanced.
+  last: Instant,
+}
+
+impl ReplayClock {
+  /// CTOR.
+  pub fn new(speed: f64, start: Instant) -> ReplayClock {
+    ReplayClock {
+      speed: speed,
+      position: Duration::from_secs(0),
+      last: start,
+    }
+  }
+
+  /// Advance to `now` and return the position in the session.
+  pub fn update(&mut self, now: Instant, paused: bool) -> Duration {
+    if !paused {
+      let elapsed = now.duration_since(self.last);
+      let nanos = (elapsed.as_secs() as f64 * 1e9
+          + elapsed.subsec_nanos() as f64) * self.speed;
+
+      self.position += Duration::new((nanos / 1e9) as u64,
+                                     (nanos % 1e9) as u32);
+    }
+    self.last = now;
+    self.position
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use dac::DacFrame;
+  use protocol::PLAYBACK_IDLE;
+  use session::SessionWriter;
+  use std::fs;
+  use std::thread;
+  use test_util::temp_path;
+
+  #[test]
+  fn test_replay_clock() {
+    let start = Instant::now();
+    let mut clock = ReplayClock::new(2.0, start);
+
+    assert_eq!(clock.update(start + Duration::from_millis(10), false),
+               Duration::from_millis(20));
+
+    // Paused time does not count.
+    assert_eq!(clock.update(start + Duration::from_millis(50), true),
+               Duration::from_millis(20));
+    assert_eq!(clock.update(start + Duration::from_millis(60), false),
+               Duration::from_millis(40));
+  }
+
+  #[test]
+  fn test_replay() {
+    let path = temp_path("replay.session");
+    let path = path.to_str().unwrap();
+
+    let frame = |timestamp: u64, point_rate: u32| SessionFrame {
+      dac: 1,
+      timestamp: Duration::from_millis(timestamp),
+      point_rate: point_rate,
+      frame: DacFrame {
+        num_points: 10,
+        point_data: vec![0u8; 18 * 10],
+      },
+    };
+
+    {
+      let mut writer = SessionWriter::create(path, 2).unwrap();
+      writer.write(&frame(0, 1_000)).unwrap();
+      writer.write(&frame(2, 2_000)).unwrap();
+      writer.finish().unwrap();
+    }
+
+    let dacs : Vec<ReplayDac> = (0 .. 2).map(|_| {
+      let status = Arc::new(RwLock::new(DacStatus::empty()));
+      (status.clone(), Arc::new(Pipeline::new(status, 1800)))
+    }).collect();
+
+    replay(path, 2.0, &dacs, &Shutdown::new()).unwrap();
+
+    let status = dacs[1].0.read().unwrap().clone();
+    assert_eq!(status.playback_state, PLAYBACK_PLAYING);
+    assert_eq!(status.point_rate, 2_000);
+    assert_eq!(status.buffer_fullness, 20);
+
+    assert_eq!(dacs[0].0.read().unwrap().playback_state, PLAYBACK_IDLE);
+
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_replay_many_rate_changes() {
+    let path = temp_path("rates.session");
+    let path = path.to_str().unwrap();
+
+    {
+      // More rate changes than the pipeline can queue at once.
+      let mut writer = SessionWriter::create(path, 1).unwrap();
+      for i in 0 .. 20 {
+        writer.write(&SessionFrame {
+          dac: 0,
+          timestamp: Duration::from_millis(0),
+          point_rate: 10_000 + i * 1_000,
+          frame: DacFrame {
+            num_points: 10,
+            point_data: vec![0u8; 18 * 10],
+          },
+        }).unwrap();
+      }
+      writer.finish().unwrap();
+    }
+
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Arc::new(Pipeline::new(status.clone(), 1800));
+    let dacs = vec![(status, pipeline.clone())];
+
+    let shutdown = Shutdown::new();
+    let shutdown2 = shutdown.clone();
+    let playback = thread::spawn(move || pipeline.process(&shutdown2));
+
+    let result = replay(path, 1.0, &dacs, &shutdown);
+
+    shutdown.stop();
+    playback.join().unwrap();
+
+    assert!(result.is_ok());
+
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! A model of the galvanometer scanners that steer the beam. Real scanners
+//! can't jump straight to each point: they accelerate, overshoot and settle,
+//! rounding off corners and long jumps at high scan speeds.
+
+use protocol::Point;
+use std::f64::consts::PI;
+
+/// Ratio of a scanner's kpps rating to its small step bandwidth in Hz. A
+/// rough rule of thumb; eg. 30kpps scanners settle at about 3kHz.
+const BANDWIDTH_PER_KPPS : f64 = 100.0;
+
+/// Largest fraction of a period of the natural frequency simulated in one
+/// step, to keep the integration stable.
+const MAX_STEP_PHASE : f64 = 0.1;
+
+/// Tracks the mirror positions of a pair of X/Y scanners. Each axis is a
+/// damped spring pulled towards the addressed point, with a speed limit.
+#[derive(Clone, Debug)]
+pub struct Scanner {
+  /// Undamped natural frequency, in radians per second.
+  omega: f64,
+  /// Damping ratio. Below 1.0 the scanners overshoot.
+  damping: f64,
+  /// Top speed, in ILDA units per second.
+  max_speed: f64,
+  /// Position of each mirror, in ILDA units.
+  position: [f64; 2],
+  /// Speed of each mirror, in ILDA units per second.
+  velocity: [f64; 2],
+  /// Last point rate seen, used while the DAC isn't reporting one.
+  point_rate: u32,
+}
+
+impl Scanner {
+  /// CTOR. `max_step` is the furthest the scanners move between points, in
+  /// ILDA units, when run at their rated speed.
+  pub fn new(kpps: u32, max_step: u16, damping: f64) -> Scanner {
+    let rated_rate = kpps as f64 * 1_000.0;
+
+    Scanner {
+      omega: 2.0 * PI * kpps as f64 * BANDWIDTH_PER_KPPS,
+      damping: damping,
+      max_speed: max_step as f64 * rated_rate,
+      position: [0.0, 0.0],
+      velocity: [0.0, 0.0],
+      point_rate: 0,
+    }
+  }
+
+  /// Where the scanners actually point while playing the points at the
+  /// point rate. Colors and other fields are left as is.
+  pub fn scan(&mut self, points: &[Point], point_rate: u32) -> Vec<Point> {
+    if point_rate > 0 {
+      self.point_rate = point_rate;
+    }
+
+    if self.point_rate == 0 {
+      return points.to_vec(); // Nothing has played yet.
+    }
+
+    let period = 1.0 / self.point_rate as f64;
+    let steps = (period * self.omega / (2.0 * PI * MAX_STEP_PHASE))
+        .ceil().max(1.0) as usize;
+    let dt = period / steps as f64;
+
+    points.iter().map(|point| {
+      let target = [point.x as f64, point.y as f64];
+
+      for _ in 0 .. steps {
+        for axis in 0 .. 2 {
+          self.step(axis, target[axis], dt);
+        }
+      }
+
+      let mut point = point.clone();
+      point.x = clamp(self.position[0]);
+      point.y = clamp(self.position[1]);
+      point
+    }).collect()
+  }
+
+  /// Advance one axis by `dt` seconds.
+  fn step(&mut self, axis: usize, target: f64, dt: f64) {
+    let error = target - self.position[axis];
+    let accel = self.omega * self.omega * error
+        - 2.0 * self.damping * self.omega * self.velocity[axis];
+
+    let velocity = self.velocity[axis] + accel * dt;
+    let velocity = velocity.max(-self.max_speed).min(self.max_speed);
+
+    self.velocity[axis] = velocity;
+    self.position[axis] += velocity * dt;
+  }
+}
+
+fn clamp(position: f64) -> i16 {
+  position.round().max(-32768.0).min(32767.0) as i16
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn points(x: i16, count: usize) -> Vec<Point> {
+    (0 .. count).map(|_| Point {
+      control: 0, x: x, y: 0, i: 0, r: 0, g: 0, b: 0, u1: 0, u2: 0,
+    }).collect()
+  }
+
+  #[test]
+  fn test_step_response() {
+    let mut scanner = Scanner::new(30, 32767, 0.5);
+    let xs : Vec<i16> = scanner.scan(&points(10_000, 100), 30_000)
+        .iter().map(|p| p.x).collect();
+
+    // The mirror lags behind the jump, overshoots, then settles.
+    assert!(xs[0] < 10_000);
+    assert!(*xs.iter().max().unwrap() > 10_000);
+    assert!((xs[99] - 10_000).abs() < 10);
+  }
+
+  #[test]
+  fn test_slew_limit() {
+    let mut scanner = Scanner::new(30, 1_000, 1.0);
+    let xs : Vec<i16> = scanner.scan(&points(30_000, 10), 30_000)
+        .iter().map(|p| p.x).collect();
+
+    for pair in xs.windows(2) {
+      assert!(pair[1] - pair[0] <= 1_001); // Allow for rounding.
+    }
+  }
+
+  #[test]
+  fn test_no_point_rate() {
+    let mut scanner = Scanner::new(30, 32767, 0.5);
+    assert_eq!(scanner.scan(&points(10_000, 2), 0), points(10_000, 2));
+  }
+}
@@ -0,0 +1,391 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Session files, for replay. A session holds the frames of points each
+//! virtual DAC played, with when they arrived and their point rate, so it
+//! can be replayed without knowing anything about the protocol. Captures
+//! (see `capture`) are turned into sessions with `convert_capture`.
+//!
+//! All integers are little endian. The file starts with a header:
+//!
+//!   - 4 bytes: magic, "EDSN"
+//!   - u16: format version (1)
+//!   - u8: number of virtual DACs
+//!
+//! Followed by any number of frames:
+//!
+//!   - u8: index of the virtual DAC
+//!   - u64: microseconds since the start of the session
+//!   - u32: point rate the frame plays at
+//!   - u16: number of points
+//!   - 18 bytes per point, as sent in a data command
+
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use capture::CaptureReader;
+use capture::RecordKind;
+use capture::dac_count;
+use capture::from_micros;
+use capture::micros;
+use dac::DacFrame;
+use dac::parse_command;
+use error::EmulatorError;
+use protocol::Command;
+use protocol::POINT_CONTROL_RATE_CHANGE;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::time::Duration;
+
+const MAGIC : &'static [u8; 4] = b"EDSN";
+const VERSION : u16 = 1;
+
+/// Size of a single point in bytes.
+const POINT_SIZE : usize = 18;
+
+/// A frame of points sent to one of the virtual DACs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionFrame {
+  /// Index of the virtual DAC.
+  pub dac: u8,
+  /// Time since the start of the session.
+  pub timestamp: Duration,
+  /// Point rate the frame plays at. Replay queues a rate change whenever
+  /// this differs from the DAC's previous frame.
+  pub point_rate: u32,
+  pub frame: DacFrame,
+}
+
+/// Writes a session file.
+pub struct SessionWriter {
+  writer: BufWriter<File>,
+}
+
+impl SessionWriter {
+  /// Create (or truncate) a session file for the given number of DACs, and
+  /// write its header.
+  pub fn create(path: &str, dacs: u8) -> Result<SessionWriter, EmulatorError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writer.write_all(MAGIC)?;
+    writer.write_u16::<LittleEndian>(VERSION)?;
+    writer.write_u8(dacs)?;
+
+    Ok(SessionWriter {
+      writer: writer,
+    })
+  }
+
+  /// Append a frame.
+  pub fn write(&mut self, frame: &SessionFrame) -> Result<(), EmulatorError> {
+    self.writer.write_u8(frame.dac)?;
+    self.writer.write_u64::<LittleEndian>(micros(frame.timestamp))?;
+    self.writer.write_u32::<LittleEndian>(frame.point_rate)?;
+    self.writer.write_u16::<LittleEndian>(frame.frame.num_points)?;
+    self.writer.write_all(&frame.frame.point_data)?;
+    Ok(())
+  }
+
+  /// Write out anything buffered, reporting any error.
+  pub fn finish(mut self) -> Result<(), EmulatorError> {
+    self.writer.flush()?;
+    Ok(())
+  }
+}
+
+/// Reads a session file one frame at a time.
+pub struct SessionReader {
+  reader: BufReader<File>,
+  dacs: u8,
+}
+
+impl SessionReader {
+  /// Open a session file and check its header.
+  pub fn open(path: &str) -> Result<SessionReader, EmulatorError> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    if &magic != MAGIC || reader.read_u16::<LittleEndian>()? != VERSION {
+      return Err(EmulatorError::ParseError);
+    }
+
+    let dacs = reader.read_u8()?;
+
+    Ok(SessionReader {
+      reader: reader,
+      dacs: dacs,
+    })
+  }
+
+  /// Number of virtual DACs in the session.
+  pub fn dacs(&self) -> usize {
+    self.dacs as usize
+  }
+
+  /// Read the next frame, or None at the end of the session.
+  pub fn next_frame(&mut self) -> Result<Option<SessionFrame>, EmulatorError> {
+    let dac = match self.reader.read_u8() {
+      Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+      Err(e) => return Err(EmulatorError::from(e)),
+      Ok(dac) => dac,
+    };
+
+    let timestamp = from_micros(self.reader.read_u64::<LittleEndian>()?);
+    let point_rate = self.reader.read_u32::<LittleEndian>()?;
+    let num_points = self.reader.read_u16::<LittleEndian>()?;
+
+    let mut point_data = vec![0u8; num_points as usize * POINT_SIZE];
+    self.reader.read_exact(&mut point_data)?;
+
+    Ok(Some(SessionFrame {
+      dac: dac,
+      timestamp: timestamp,
+      point_rate: point_rate,
+      frame: DacFrame {
+        num_points: num_points,
+        point_data: point_data,
+      },
+    }))
+  }
+}
+
+/// Work out the frames each DAC played from the commands in a capture, and
+/// save them as a session. Returns the number of frames written.
+///
+/// Frames sent before playback began are timestamped when it began. This
+/// assumes the DAC had room for every frame; clients rarely overfill it.
+pub fn convert_capture(capture_path: &str, session_path: &str)
+                       -> Result<u64, EmulatorError> {
+  let dacs = dac_count(capture_path)?;
+  let mut reader = CaptureReader::open(capture_path)?;
+  let mut writer = SessionWriter::create(session_path, dacs as u8)?;
+
+  let mut states : Vec<ConvertState> = (0 .. dacs).map(|_| {
+    ConvertState::new()
+  }).collect();
+
+  let mut written = 0;
+
+  while let Some(record) = reader.next_record()? {
+    let state = &mut states[record.dac as usize];
+
+    let command = match record.kind {
+      // Same as the dac does between client connections.
+      RecordKind::Open | RecordKind::Close => {
+        *state = ConvertState::new();
+        continue;
+      },
+      RecordKind::Command => match parse_command(&record.payload) {
+        Err(_) => continue, // The client sent garbage.
+        Ok(command) => command,
+      },
+    };
+
+    let frames = match command {
+      Command::Prepare => {
+        *state = ConvertState::new();
+        state.prepared = true;
+        Vec::new()
+      },
+      Command::Stop | Command::EmergencyStop => {
+        *state = ConvertState::new();
+        Vec::new()
+      },
+      Command::QueueRateChange { point_rate } => {
+        state.rate_changes.push_back(point_rate);
+        Vec::new()
+      },
+      Command::Begin { point_rate, .. } if state.prepared => {
+        state.point_rate = Some(point_rate);
+
+        let held : Vec<DacFrame> = state.held.drain(..).collect();
+        let mut frames = Vec::new();
+        for frame in held {
+          frames.extend(state.split(frame));
+        }
+        frames
+      },
+      Command::Data { num_points, ref point_data } if state.prepared => {
+        let frame = DacFrame {
+          num_points: num_points,
+          point_data: point_data.clone(),
+        };
+
+        if state.point_rate.is_some() {
+          state.split(frame)
+        } else {
+          state.held.push(frame);
+          Vec::new()
+        }
+      },
+      _ => Vec::new(),
+    };
+
+    for (point_rate, frame) in frames {
+      writer.write(&SessionFrame {
+        dac: record.dac,
+        timestamp: record.timestamp,
+        point_rate: point_rate,
+        frame: frame,
+      })?;
+      written += 1;
+    }
+  }
+
+  writer.finish()?;
+  Ok(written)
+}
+
+/// What a DAC is doing while a capture is converted.
+struct ConvertState {
+  /// Whether data is accepted; set by Prepare.
+  prepared: bool,
+  /// The point rate, once playback has begun.
+  point_rate: Option<u32>,
+  /// Point rates queued by the client.
+  rate_changes: VecDeque<u32>,
+  /// Frames sent before playback began.
+  held: Vec<DacFrame>,
+}
+
+impl ConvertState {
+  fn new() -> ConvertState {
+    ConvertState {
+      prepared: false,
+      point_rate: None,
+      rate_changes: VecDeque::new(),
+      held: Vec::new(),
+    }
+  }
+
+  /// Split a frame after each point that changes the point rate, pairing
+  /// each part with the rate it plays at. The rate change control bits are
+  /// cleared, since the frames carry their rates.
+  fn split(&mut self, frame: DacFrame) -> Vec<(u32, DacFrame)> {
+    let mut parts = Vec::new();
+    let mut point_data = Vec::new();
+
+    for point in frame.point_data.chunks(POINT_SIZE) {
+      let control = LittleEndian::read_u16(point);
+      let start = point_data.len();
+
+      point_data.extend_from_slice(point);
+
+      if control & POINT_CONTROL_RATE_CHANGE == 0 {
+        continue;
+      }
+
+      LittleEndian::write_u16(&mut point_data[start ..],
+                              control & !POINT_CONTROL_RATE_CHANGE);
+
+      if let Some(point_rate) = self.rate_changes.pop_front() {
+        parts.push(self.part(point_data));
+        point_data = Vec::new();
+        self.point_rate = Some(point_rate);
+      }
+    }
+
+    if !point_data.is_empty() {
+      parts.push(self.part(point_data));
+    }
+
+    parts
+  }
+
+  fn part(&self, point_data: Vec<u8>) -> (u32, DacFrame) {
+    let frame = DacFrame {
+      num_points: (point_data.len() / POINT_SIZE) as u16,
+      point_data: point_data,
+    };
+    (self.point_rate.unwrap_or(0), frame)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use capture::Recorder;
+  use std::fs;
+  use test_util::temp_path;
+
+  #[test]
+  fn test_session_round_trip() {
+    let path = temp_path("test.session");
+    let path = path.to_str().unwrap();
+
+    let frame = SessionFrame {
+      dac: 1,
+      timestamp: Duration::from_millis(1_500),
+      point_rate: 30_000,
+      frame: DacFrame {
+        num_points: 2,
+        point_data: (0 .. 36).collect(),
+      },
+    };
+
+    {
+      let mut writer = SessionWriter::create(path, 2).unwrap();
+      writer.write(&frame).unwrap();
+      writer.finish().unwrap();
+    }
+
+    let mut reader = SessionReader::open(path).unwrap();
+    assert_eq!(reader.dacs(), 2);
+    assert_eq!(reader.next_frame().unwrap(), Some(frame));
+    assert_eq!(reader.next_frame().unwrap(), None);
+
+    fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_convert_capture() {
+    let capture = temp_path("convert.capture");
+    let capture = capture.to_str().unwrap();
+    let session = temp_path("convert.session");
+    let session = session.to_str().unwrap();
+
+    {
+      // Ten points, the fourth of which changes the point rate.
+      let mut data = vec![0x64, 10, 0]; // 'd', 10 points
+      data.extend_from_slice(&[0u8; 18 * 10]);
+      data[3 + 18 * 3 + 1] = 0x80;
+
+      let recorder = Recorder::create(capture).unwrap();
+      recorder.open(0, "127.0.0.1:5000".parse().unwrap()).unwrap();
+      recorder.command(0, &data).unwrap(); // Ignored until prepared.
+      recorder.command(0, &[0x70]).unwrap(); // 'p'
+      recorder.command(0, &[0x71, 0x20, 0x4e, 0, 0]).unwrap(); // 'q'
+      recorder.command(0, &data).unwrap();
+      recorder.command(0, &[0x62, 0, 0, 0x10, 0x27, 0, 0]).unwrap(); // 'b'
+    }
+
+    assert_eq!(convert_capture(capture, session).unwrap(), 2);
+
+    let mut reader = SessionReader::open(session).unwrap();
+    assert_eq!(reader.dacs(), 1);
+
+    let first = reader.next_frame().unwrap().unwrap();
+    assert_eq!(first.point_rate, 10_000);
+    assert_eq!(first.frame.num_points, 4);
+
+    let second = reader.next_frame().unwrap().unwrap();
+    assert_eq!(second.point_rate, 20_000);
+    assert_eq!(second.frame.num_points, 6);
+    assert_eq!(second.timestamp, first.timestamp);
+
+    // The frames carry the rates, not the points.
+    assert_eq!(first.frame.point_data[3 * 18 + 1], 0);
+
+    assert_eq!(reader.next_frame().unwrap(), None);
+
+    fs::remove_file(capture).unwrap();
+    fs::remove_file(session).unwrap();
+  }
+}
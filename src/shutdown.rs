@@ -0,0 +1,52 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often `Shutdown::wait` checks whether it should stop sleeping.
+const POLL_INTERVAL_MS : u64 = 10;
+
+/// Tells long-running threads to finish up. Clones share the same flag.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+  stopped: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+  /// CTOR.
+  pub fn new() -> Shutdown {
+    Shutdown {
+      stopped: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  /// Ask every thread sharing this signal to stop.
+  pub fn stop(&self) {
+    self.stopped.store(true, Ordering::SeqCst);
+  }
+
+  /// Whether threads should stop.
+  pub fn is_stopped(&self) -> bool {
+    self.stopped.load(Ordering::SeqCst)
+  }
+
+  /// Sleep for the duration, waking early if asked to stop.
+  /// Returns whether threads should stop.
+  pub fn wait(&self, duration: Duration) -> bool {
+    let start = Instant::now();
+
+    while !self.is_stopped() {
+      let elapsed = start.elapsed();
+      if elapsed >= duration {
+        return false;
+      }
+      let remaining = duration - elapsed;
+      thread::sleep(remaining.min(Duration::from_millis(POLL_INTERVAL_MS)));
+    }
+    true
+  }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Sources of points other than the network streamer. The real DAC can play
+//! ILDA files from its SD card, or run its internal abstract generator,
+//! instead of points streamed by a client.
+
+use error::EmulatorError;
+use pipeline::Pipeline;
+use protocol::DacStatus;
+use protocol::LIGHT_ENGINE_READY;
+use protocol::PLAYBACK_IDLE;
+use protocol::PLAYBACK_PLAYING;
+use protocol::PLAYBACK_PREPARED;
+use protocol::Point;
+use shutdown::Shutdown;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// How often a source tops up the point buffer.
+const FEED_INTERVAL_MS : u64 = 5;
+
+/// Produces points for the playback system, in place of a client.
+pub trait PointSource {
+  /// Source reported in the DAC status, eg. `SOURCE_ILDA`.
+  fn source(&self) -> u8;
+
+  /// The next points to play, at most `max` of them.
+  fn next_points(&mut self, max: usize) -> Vec<Point>;
+}
+
+/// Keep the DAC playing points from the source at the point rate, until
+/// shutdown.
+pub fn source_thread<S: PointSource>(mut source: S, point_rate: u32,
+                                     status: Arc<RwLock<DacStatus>>,
+                                     pipeline: Arc<Pipeline>,
+                                     shutdown: &Shutdown) {
+  while !shutdown.wait(Duration::from_millis(FEED_INTERVAL_MS)) {
+    feed(&mut source, point_rate, &status, &pipeline).unwrap(); // Fatal error.
+  }
+}
+
+/// Top up the buffer from the source, starting playback if need be.
+pub fn feed<S: PointSource>(source: &mut S, point_rate: u32,
+                            status: &RwLock<DacStatus>, pipeline: &Pipeline)
+                            -> Result<(), EmulatorError> {
+  // NB: Lock status before the queues, same as the network thread.
+  let mut status = status.write()?;
+
+  status.source = source.source();
+
+  if status.light_engine_state != LIGHT_ENGINE_READY {
+    return Ok(()); // Wait for the E-Stop to be cleared.
+  }
+
+  if status.playback_state == PLAYBACK_IDLE {
+    // Start over, eg. after an E-Stop or underflow.
+    pipeline.clear()?;
+
+    status.playback_state = PLAYBACK_PREPARED;
+    status.playback_flags = 0;
+    status.point_count = 0;
+  }
+
+  let room = pipeline.buffer_capacity() - pipeline.queue_size()?;
+
+  if room > 0 {
+    pipeline.enqueue_points(source.next_points(room))?;
+  }
+
+  status.buffer_fullness = pipeline.queue_size()? as u16;
+
+  if status.playback_state == PLAYBACK_PREPARED && status.buffer_fullness > 0 {
+    status.playback_state = PLAYBACK_PLAYING;
+    status.point_rate = point_rate;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use protocol::SOURCE_GENERATOR;
+
+  struct Origin;
+
+  impl PointSource for Origin {
+    fn source(&self) -> u8 {
+      SOURCE_GENERATOR
+    }
+
+    fn next_points(&mut self, max: usize) -> Vec<Point> {
+      (0 .. max).map(|_| Point {
+        control: 0, x: 0, y: 0, i: 0, r: 0, g: 0, b: 0, u1: 0, u2: 0,
+      }).collect()
+    }
+  }
+
+  #[test]
+  fn test_feed() {
+    let status = Arc::new(RwLock::new(DacStatus::empty()));
+    let pipeline = Pipeline::new(status.clone(), 100);
+
+    feed(&mut Origin, 20_000, &status, &pipeline).unwrap();
+
+    let status = status.read().unwrap().clone();
+    assert_eq!(status.source, SOURCE_GENERATOR);
+    assert_eq!(status.playback_state, PLAYBACK_PLAYING);
+    assert_eq!(status.point_rate, 20_000);
+    assert_eq!(status.buffer_fullness, 100);
+  }
+}
@@ -0,0 +1,216 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Counts what each DAC receives and plays, for the statistics HUD.
+
+use protocol::ResponseState;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Milliseconds between samples of a DAC's stats.
+const SAMPLE_INTERVAL_MS : u64 = 100;
+
+/// Rates are averaged over this many milliseconds.
+const RATE_WINDOW_MS : u64 = 1_000;
+
+/// Number of buffer fullness samples kept, ten seconds' worth.
+pub const HISTORY_LENGTH : usize = 100;
+
+/// Running totals for a DAC, kept by its pipeline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+  /// Whether a client is connected.
+  pub connected: bool,
+  /// Points accepted into the buffer, from any source.
+  pub points_received: u64,
+  /// Points played.
+  pub points_played: u64,
+  /// How long the points played take at the rates they were played at.
+  pub time_played: Duration,
+  /// Commands received from the client.
+  pub packets: u64,
+  /// Commands answered with anything but an ACK.
+  pub naks: u64,
+  /// Number of times playback ran out of points.
+  pub underflows: u64,
+}
+
+impl Stats {
+  /// CTOR.
+  pub fn new() -> Stats {
+    Stats {
+      connected: false,
+      points_received: 0,
+      points_played: 0,
+      time_played: Duration::from_secs(0),
+      packets: 0,
+      naks: 0,
+      underflows: 0,
+    }
+  }
+
+  /// Count a command from the client, and the response it got.
+  pub fn count_response(&mut self, response: ResponseState) {
+    self.packets += 1;
+
+    if response != ResponseState::Ack {
+      self.naks += 1;
+    }
+  }
+}
+
+/// Per second rates derived from stats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rates {
+  pub points_received: f64,
+  pub points_played: f64,
+  pub packets: f64,
+}
+
+/// Samples a DAC's stats over time, for rates and buffer fullness history.
+pub struct StatsMeter {
+  /// Samples within the rate window, oldest first.
+  samples: VecDeque<(Instant, Stats)>,
+  /// Buffer fullness from 0.0 to 1.0 at each sample, oldest first.
+  fullness: VecDeque<f32>,
+}
+
+impl StatsMeter {
+  /// CTOR.
+  pub fn new() -> StatsMeter {
+    StatsMeter {
+      samples: VecDeque::new(),
+      fullness: VecDeque::new(),
+    }
+  }
+
+  /// Whether a new sample should be taken.
+  pub fn is_due(&self, now: Instant) -> bool {
+    match self.samples.back() {
+      None => true,
+      Some(&(time, _)) => {
+        now.duration_since(time) >= Duration::from_millis(SAMPLE_INTERVAL_MS)
+      },
+    }
+  }
+
+  /// Record a sample of the stats and buffer fullness.
+  pub fn sample(&mut self, now: Instant, stats: Stats, fullness: f32) {
+    self.samples.push_back((now, stats));
+
+    let window = Duration::from_millis(RATE_WINDOW_MS);
+    while self.samples.len() > 2
+        && now.duration_since(self.samples[1].0) >= window {
+      self.samples.pop_front();
+    }
+
+    self.fullness.push_back(fullness.max(0.0).min(1.0));
+
+    while self.fullness.len() > HISTORY_LENGTH {
+      self.fullness.pop_front();
+    }
+  }
+
+  /// The most recent stats, if any were sampled.
+  pub fn latest(&self) -> Option<&Stats> {
+    self.samples.back().map(|&(_, ref stats)| stats)
+  }
+
+  /// Rates over the last second or so. Zero until two samples are taken.
+  pub fn rates(&self) -> Rates {
+    let zero = Rates {
+      points_received: 0.0,
+      points_played: 0.0,
+      packets: 0.0,
+    };
+
+    let (first, last) = match (self.samples.front(), self.samples.back()) {
+      (Some(first), Some(last)) => (first, last),
+      _ => return zero,
+    };
+
+    let elapsed = last.0.duration_since(first.0);
+    let seconds = elapsed.as_secs() as f64
+        + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+
+    if seconds == 0.0 {
+      return zero;
+    }
+
+    // NB: Counters restart when a DAC is restarted.
+    let rate = |a: u64, b: u64| b.saturating_sub(a) as f64 / seconds;
+
+    Rates {
+      points_received: rate(first.1.points_received, last.1.points_received),
+      points_played: rate(first.1.points_played, last.1.points_played),
+      packets: rate(first.1.packets, last.1.packets),
+    }
+  }
+
+  /// Buffer fullness history, from 0.0 to 1.0, oldest first.
+  pub fn fullness(&self) -> &VecDeque<f32> {
+    &self.fullness
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use protocol::ResponseState;
+  use std::time::Duration;
+  use std::time::Instant;
+
+  #[test]
+  fn test_count_response() {
+    let mut stats = Stats::new();
+    stats.count_response(ResponseState::Ack);
+    stats.count_response(ResponseState::BufferFull);
+    stats.count_response(ResponseState::InvalidCommand);
+
+    assert_eq!(stats.packets, 3);
+    assert_eq!(stats.naks, 2);
+  }
+
+  #[test]
+  fn test_rates() {
+    let start = Instant::now();
+    let mut meter = StatsMeter::new();
+    let mut stats = Stats::new();
+
+    assert!(meter.is_due(start));
+    meter.sample(start, stats.clone(), 0.0);
+    assert_eq!(meter.rates().points_played, 0.0);
+
+    assert!(!meter.is_due(start + Duration::from_millis(50)));
+
+    for i in 1 .. 21 {
+      stats.points_played += 3_000;
+      stats.packets += 10;
+      meter.sample(start + Duration::from_millis(i * 100), stats.clone(),
+                   0.5);
+    }
+
+    // 30kpps, averaged over the last second.
+    let rates = meter.rates();
+    assert!((rates.points_played - 30_000.0).abs() < 1.0);
+    assert!((rates.packets - 100.0).abs() < 0.1);
+    assert_eq!(rates.points_received, 0.0);
+    assert_eq!(meter.latest(), Some(&stats));
+  }
+
+  #[test]
+  fn test_fullness_history() {
+    let start = Instant::now();
+    let mut meter = StatsMeter::new();
+
+    for i in 0 .. 150 {
+      meter.sample(start + Duration::from_millis(i * 100), Stats::new(),
+                   i as f32 / 100.0);
+    }
+
+    let fullness = meter.fullness();
+    assert_eq!(fullness.len(), 100);
+    assert_eq!(fullness[0], 0.5);
+    assert_eq!(fullness[99], 1.0);
+  }
+}
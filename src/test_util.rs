@@ -0,0 +1,21 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Helpers shared by the unit tests.
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// A path in the temp directory ending in `name`, unique to this process and
+/// time, so concurrent runs don't clobber each other's files. Names must
+/// differ between tests.
+pub fn temp_path(name: &str) -> PathBuf {
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+      .map(|d| d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64)
+      .unwrap_or(0);
+
+  env::temp_dir().join(format!("etherdream-emulator-{}-{}-{}", process::id(),
+                               nanos, name))
+}
@@ -0,0 +1,181 @@
+// Copyright (c) 2016 Brandon Thomas <bt@brand.io>, <echelon@gmail.com>
+
+//! Records what is drawn as video at a fixed frame rate, either as a
+//! numbered PNG sequence or as a y4m stream for encoders such as ffmpeg.
+//! Frames follow the DACs' playback position, so time stands still while
+//! paused or idle.
+
+use error::EmulatorError;
+use raster::Canvas;
+use render::numbered_path;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Records frames to a file or sequence of files.
+pub struct VideoRecorder {
+  path: String,
+  /// Frames per second.
+  fps: u32,
+  /// The y4m stream, or None when recording PNGs.
+  y4m: Option<BufWriter<File>>,
+  /// Playback position when recording started.
+  start: Option<Duration>,
+  /// Number of frames recorded.
+  frames: u64,
+}
+
+impl VideoRecorder {
+  /// Start recording. Paths ending in `.y4m` get a y4m stream, anything
+  /// else a numbered PNG per frame. Frames must all be the given size.
+  pub fn create(path: &str, fps: u32, size: [u32; 2])
+                -> Result<VideoRecorder, EmulatorError> {
+    let is_y4m = Path::new(path).extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase() == "y4m")
+        .unwrap_or(false);
+
+    let y4m = if is_y4m {
+      let mut writer = BufWriter::new(File::create(path)?);
+      // NB: Full chroma (4:4:4), so colored lines a pixel wide survive.
+      write!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n",
+             size[0], size[1], fps)?;
+      Some(writer)
+    } else {
+      None
+    };
+
+    Ok(VideoRecorder {
+      path: path.to_string(),
+      fps: fps,
+      y4m: y4m,
+      start: None,
+      frames: 0,
+    })
+  }
+
+  /// Number of frames recorded.
+  pub fn frames(&self) -> u64 {
+    self.frames
+  }
+
+  /// Number of frames that have come due by a playback position (see
+  /// `Stats::time_played`) and not been recorded. The first frame is due
+  /// immediately.
+  pub fn frames_due(&mut self, position: Duration) -> u64 {
+    let start = match self.start {
+      None => {
+        self.start = Some(position);
+        position
+      },
+      Some(start) => start,
+    };
+
+    let position = if position > start {
+      position - start
+    } else {
+      Duration::from_secs(0)
+    };
+    let fps = self.fps as u64;
+    let due = position.as_secs() * fps
+        + position.subsec_nanos() as u64 * fps / 1_000_000_000 + 1;
+
+    due.saturating_sub(self.frames)
+  }
+
+  /// Record the canvas as the next frame.
+  pub fn write_frame(&mut self, canvas: &Canvas) -> Result<(), EmulatorError> {
+    self.frames += 1;
+
+    match self.y4m {
+      None => canvas.write_png(&numbered_path(&self.path,
+                                              self.frames as usize)),
+      Some(ref mut writer) => {
+        writer.write_all(b"FRAME\n")?;
+        writer.write_all(&to_ycbcr(&canvas.to_rgb()))?;
+        Ok(())
+      },
+    }
+  }
+}
+
+/// Convert RGB pixels to planar Y, Cb and Cr in studio range (BT.601).
+fn to_ycbcr(rgb: &[u8]) -> Vec<u8> {
+  let pixels = rgb.len() / 3;
+  let mut planes = vec![0u8; pixels * 3];
+
+  for (i, pixel) in rgb.chunks(3).enumerate() {
+    let r = pixel[0] as f32 / 255.0;
+    let g = pixel[1] as f32 / 255.0;
+    let b = pixel[2] as f32 / 255.0;
+
+    let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+    let cb = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+    let cr = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+
+    planes[i] = y.round() as u8;
+    planes[pixels + i] = cb.round() as u8;
+    planes[pixels * 2 + i] = cr.round() as u8;
+  }
+
+  planes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use graphics::Graphics;
+  use std::fs;
+  use std::io::Read;
+  use std::time::Duration;
+  use test_util::temp_path;
+
+  #[test]
+  fn test_frames_due() {
+    let path = temp_path("due.png");
+    let mut video = VideoRecorder::create(path.to_str().unwrap(), 30,
+                                          [1, 1]).unwrap();
+
+    // Recording starts partway through playback.
+    let start = Duration::from_secs(5);
+    assert_eq!(video.frames_due(start), 1);
+
+    // Three frames have come due after 0.1s at 30fps, plus the first.
+    let position = start + Duration::from_millis(100);
+    assert_eq!(video.frames_due(position), 4);
+
+    video.frames = 4;
+    assert_eq!(video.frames_due(position), 0);
+  }
+
+  #[test]
+  fn test_y4m() {
+    let path = temp_path("video.y4m");
+    let path = path.to_str().unwrap();
+
+    {
+      let mut canvas = Canvas::new(2, 1);
+      canvas.clear_color([1.0, 1.0, 1.0, 1.0]);
+
+      let mut video = VideoRecorder::create(path, 25, [2, 1]).unwrap();
+      video.write_frame(&canvas).unwrap();
+      video.write_frame(&canvas).unwrap();
+      assert_eq!(video.frames(), 2);
+    }
+
+    let mut bytes = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+
+    let header = b"YUV4MPEG2 W2 H1 F25:1 Ip A1:1 C444\n";
+    assert_eq!(&bytes[.. header.len()], &header[..]);
+
+    // Two frames of white: full luma, neutral chroma.
+    let frame = &bytes[header.len() ..];
+    assert_eq!(frame.len(), 2 * (6 + 6));
+    assert_eq!(&frame[.. 12], b"FRAME\n\xeb\xeb\x80\x80\x80\x80");
+
+    fs::remove_file(path).unwrap();
+  }
+}